@@ -0,0 +1,457 @@
+//! Credit earning and upkeep, and the balancing knobs of `config.toml`
+
+use crate::k8s::{GameResources, get_unit_target, get_unit_type, processor_targets, target_key};
+use crate::settings::Settings;
+use crate::state::{GameState, SaveGame};
+use k8s_openapi::api::core::v1::Pod;
+use macroquad::experimental::collections::storage;
+use macroquad::prelude::coroutines::start_coroutine;
+use macroquad::prelude::coroutines::wait_seconds;
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const GAME_CONFIG_PATH: &str = "config.toml";
+/// Shortest earning and upkeep interval, shorter ones in [`GAME_CONFIG_PATH`]
+/// are raised to it
+const MIN_INTERVAL_SECONDS: f32 = 0.01;
+
+/// Scale yield and upkeep by the cluster size, so the economy feels the same on
+/// a one node kind cluster and on a large production cluster
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EconomyScaling {
+    #[default]
+    Off,
+    /// Derive the scale from the cluster at session start
+    Session,
+    /// Derive the scale again on every cluster update
+    Live,
+}
+
+impl EconomyScaling {
+    /// Clusters of this many nodes keep the unscaled economy
+    const REFERENCE_NODES: f64 = 3.;
+
+    pub fn scale(game_resources: &GameResources) -> f64 {
+        (game_resources.nodes.len() as f64 / Self::REFERENCE_NODES)
+            .sqrt()
+            .clamp(0.5, 10.)
+    }
+}
+
+const DEFAULT_IMAGE: &str = "registry.cube-harvest.invalid/miner:42";
+
+/// Balancing knobs loaded from [`GAME_CONFIG_PATH`], unlike [`Settings`] it's
+/// only read and never written by the game
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct GameConfig {
+    /// Number of miners a processor can take, extra miners earn nothing
+    pub processor_capacity: usize,
+    /// Seconds between credit earning ticks
+    pub earn_interval_seconds: f32,
+    /// Seconds between upkeep ticks, each unit costs the upkeep of its type
+    pub consume_interval_seconds: f32,
+    /// Credits a miner costs per upkeep tick
+    pub miner_upkeep: usize,
+    /// Credits a processor costs per upkeep tick
+    pub processor_upkeep: usize,
+    /// Shortest time between two cluster snapshots sent to the game
+    pub update_interval_millis: u64,
+    /// Seconds the credits may stay unable to cover the upkeep before the game
+    /// is over
+    pub game_over_grace_seconds: f32,
+    /// CPU request of created units, as a Kubernetes quantity
+    pub cpu_request: String,
+    /// Memory request of created units, as a Kubernetes quantity
+    pub mem_request: String,
+    /// Container images of created miners and processors, e.g. a mirror on
+    /// an internal registry
+    pub miner_image: String,
+    pub processor_image: String,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            processor_capacity: 3,
+            earn_interval_seconds: 1.,
+            consume_interval_seconds: 3.,
+            miner_upkeep: 1,
+            processor_upkeep: 3,
+            update_interval_millis: 100,
+            game_over_grace_seconds: 10.,
+            cpu_request: "100m".to_string(),
+            mem_request: "64Mi".to_string(),
+            miner_image: DEFAULT_IMAGE.to_string(),
+            processor_image: DEFAULT_IMAGE.to_string(),
+        }
+    }
+}
+
+impl GameConfig {
+    /// Missing fields get their defaults, a missing or malformed file is
+    /// ignored entirely
+    pub fn load() -> Self {
+        let Ok(content) = std::fs::read_to_string(GAME_CONFIG_PATH) else {
+            return Self::default();
+        };
+        match toml::from_str::<Self>(&content) {
+            Ok(config) => config.with_valid_intervals(),
+            Err(err) => {
+                println!("ignore malformed {GAME_CONFIG_PATH}: {err}");
+                Self::default()
+            }
+        }
+    }
+
+    /// Raise the intervals below [`MIN_INTERVAL_SECONDS`], the economy would
+    /// tick forever on a zero interval
+    fn with_valid_intervals(mut self) -> Self {
+        for (name, interval) in [
+            ("earn_interval_seconds", &mut self.earn_interval_seconds),
+            (
+                "consume_interval_seconds",
+                &mut self.consume_interval_seconds,
+            ),
+        ] {
+            if interval.is_nan() || *interval < MIN_INTERVAL_SECONDS {
+                println!(
+                    "{name} = {interval} in {GAME_CONFIG_PATH} is too short, using {MIN_INTERVAL_SECONDS}"
+                );
+                *interval = MIN_INTERVAL_SECONDS;
+            }
+        }
+        self
+    }
+}
+
+pub fn start_autosave() {
+    start_coroutine(autosave());
+}
+
+/// Write the progress to disk now and then, in case the game doesn't exit
+/// cleanly
+async fn autosave() {
+    let mut saved = SaveGame::of(&storage::get::<GameState>());
+    loop {
+        wait_seconds(5.).await;
+        let save = SaveGame::of(&storage::get::<GameState>());
+        if save != saved {
+            save.save();
+            saved = save;
+        }
+    }
+}
+
+/// Time of play since the last earning and upkeep ticks. The main loop
+/// advances it every frame with `get_time()`, so the ticks keep their pace
+/// however the frames are paced.
+#[derive(Debug, Default)]
+pub struct EconomyClock {
+    last_time: Option<f64>,
+    since_earn: f64,
+    since_consume: f64,
+}
+
+impl EconomyClock {
+    /// Run the ticks that came due since the last call, time spent paused
+    /// doesn't count
+    pub fn update(&mut self, now: f64) {
+        let paused = storage::get::<GameState>().paused;
+        let demo_speed = storage::get::<Settings>().demo_speed() as f64;
+        let (earn_interval, consume_interval) = {
+            let game_config = storage::get::<GameConfig>();
+            (
+                game_config.earn_interval_seconds as f64 / demo_speed,
+                game_config.consume_interval_seconds as f64 / demo_speed,
+            )
+        };
+        let (earn_ticks, consume_ticks) =
+            self.advance(now, paused, earn_interval, consume_interval);
+        for _ in 0..earn_ticks {
+            earn_credits(earn_interval as f32);
+        }
+        for _ in 0..consume_ticks {
+            consume_credits(consume_interval as f32);
+        }
+    }
+
+    /// Move the clock to `now`, returning how many earning and upkeep ticks
+    /// came due
+    fn advance(
+        &mut self,
+        now: f64,
+        paused: bool,
+        earn_interval: f64,
+        consume_interval: f64,
+    ) -> (usize, usize) {
+        let elapsed = now - self.last_time.unwrap_or(now);
+        self.last_time = Some(now);
+        if paused {
+            return (0, 0);
+        }
+        self.since_earn += elapsed;
+        self.since_consume += elapsed;
+        (
+            take_ticks(&mut self.since_earn, earn_interval),
+            take_ticks(&mut self.since_consume, consume_interval),
+        )
+    }
+}
+
+/// Whole `interval`s in `since`, which keeps the remainder
+fn take_ticks(since: &mut f64, interval: f64) -> usize {
+    let interval = interval.max(MIN_INTERVAL_SECONDS as f64);
+    let ticks = (*since / interval).floor();
+    *since -= ticks * interval;
+    ticks as usize
+}
+
+fn earn_credits(interval: f32) {
+    let earned_credits = storage::get::<GameResources>().credit_rate;
+    let mut game_state = storage::get_mut::<GameState>();
+    game_state.apply_credit_tick(earned_credits, 0);
+    game_state.last_earn_rate = game_state.rate_per_second(earned_credits, interval);
+}
+
+fn consume_credits(interval: f32) {
+    let game_config = storage::get::<GameConfig>();
+    let consumed_credits = compute_upkeep(&storage::get::<GameResources>().pods, &game_config);
+    let mut game_state = storage::get_mut::<GameState>();
+    game_state.apply_credit_tick(0, consumed_credits);
+    game_state.last_consume_rate = game_state.rate_per_second(consumed_credits, interval);
+    if game_state.credits == 0 && consumed_credits > 0 {
+        game_state.broke_for += game_config.consume_interval_seconds;
+        if game_state.broke_for >= game_config.game_over_grace_seconds {
+            game_state.should_game_over = true;
+        }
+    } else {
+        game_state.broke_for = 0.;
+    }
+}
+
+/// Miners earning a credit per tick, mapped to the index in `pods` of their
+/// processor. Each processor takes up to `processor_capacity` miners, in
+/// pod order.
+pub fn earning_miners(pods: &[Pod], processor_capacity: usize) -> HashMap<&str, usize> {
+    // a dual-stack processor can be targeted by any of its IPs, a StatefulSet
+    // processor by its Service name too
+    let mut processor_of_target = HashMap::new();
+    for (i, p) in pods.iter().enumerate() {
+        if get_unit_type(p).as_deref() == Some("processor") {
+            for target in processor_targets(p) {
+                processor_of_target.insert(target, i);
+            }
+        }
+    }
+
+    let mut miners = HashMap::new();
+    let mut load = HashMap::<usize, usize>::new();
+    for p in pods {
+        if get_unit_type(p).as_deref() != Some("miner") {
+            continue;
+        }
+        let (Some(name), Some(target)) = (p.metadata.name.as_deref(), get_unit_target(p)) else {
+            continue;
+        };
+        if let Some(&i) = processor_of_target.get(target_key(&target)) {
+            let load = load.entry(i).or_default();
+            if *load < processor_capacity {
+                *load += 1;
+                miners.insert(name, i);
+            }
+        }
+    }
+    miners
+}
+
+/// Credits `pods` earn per tick, one per miner with room on its processor
+pub fn compute_credit_rate(pods: &[Pod], processor_capacity: usize) -> usize {
+    earning_miners(pods, processor_capacity).len()
+}
+
+/// Credits `pods` cost per upkeep tick by their unit type, other pods in the
+/// namespace cost a credit each
+pub fn compute_upkeep(pods: &[Pod], game_config: &GameConfig) -> usize {
+    pods.iter()
+        .map(|p| match get_unit_type(p).as_deref() {
+            Some("miner") => game_config.miner_upkeep,
+            Some("processor") => game_config.processor_upkeep,
+            _ => 1,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn clock_catches_up_on_slow_frames() {
+        let mut clock = EconomyClock::default();
+        assert_eq!(clock.advance(10., false, 1., 3.), (0, 0));
+        assert_eq!(clock.advance(13.5, false, 1., 3.), (3, 1));
+        assert_eq!(clock.advance(14., false, 1., 3.), (1, 0));
+    }
+
+    #[test]
+    fn clock_stops_while_paused() {
+        let mut clock = EconomyClock::default();
+        clock.advance(0., false, 1., 3.);
+        assert_eq!(clock.advance(0.5, false, 1., 3.), (0, 0));
+        assert_eq!(clock.advance(100., true, 1., 3.), (0, 0));
+        assert_eq!(clock.advance(100.5, false, 1., 3.), (1, 0));
+    }
+
+    #[test]
+    fn zero_intervals_tick_at_the_shortest_interval() {
+        let mut clock = EconomyClock::default();
+        clock.advance(0., false, 0., -1.);
+        let (earn_ticks, consume_ticks) = clock.advance(1., false, 0., -1.);
+        assert!((99..=100).contains(&earn_ticks));
+        assert!((99..=100).contains(&consume_ticks));
+
+        let config = GameConfig {
+            earn_interval_seconds: 0.,
+            consume_interval_seconds: -3.,
+            ..GameConfig::default()
+        }
+        .with_valid_intervals();
+        assert_eq!(config.earn_interval_seconds, MIN_INTERVAL_SECONDS);
+        assert_eq!(config.consume_interval_seconds, MIN_INTERVAL_SECONDS);
+    }
+
+    const CAPACITY: usize = 3;
+
+    fn processor(name: &str, ip: Option<&str>) -> Pod {
+        let mut pod = json!({
+            "metadata": {
+                "name": name,
+                "labels": { "cube-harvest.io/unit-type": "processor" },
+            },
+            "spec": { "containers": [{ "name": "astro-unit" }] },
+        });
+        if let Some(ip) = ip {
+            pod["status"] = json!({ "podIP": ip });
+        }
+        serde_json::from_value(pod).unwrap()
+    }
+
+    fn miner(name: &str, target: &str) -> Pod {
+        serde_json::from_value(json!({
+            "metadata": {
+                "name": name,
+                "labels": { "cube-harvest.io/unit-type": "miner" },
+            },
+            "spec": {
+                "containers": [{
+                    "name": "astro-unit",
+                    "env": [{ "name": "TARGET", "value": target }],
+                }],
+            },
+        }))
+        .unwrap()
+    }
+
+    /// A processor at 10.0.0.1 and `miners` miners targeting it
+    fn processor_with_miners(miners: usize) -> Vec<Pod> {
+        let mut pods = vec![processor("processor-1", Some("10.0.0.1"))];
+        pods.extend((0..miners).map(|i| miner(&format!("miner-{i}"), "10.0.0.1")));
+        pods
+    }
+
+    #[test]
+    fn no_pods_earn_nothing() {
+        assert_eq!(compute_credit_rate(&[], CAPACITY), 0);
+    }
+
+    #[test]
+    fn miners_without_processors_earn_nothing() {
+        let pods = [miner("miner-1", "10.0.0.1"), miner("miner-2", "10.0.0.2")];
+        assert_eq!(compute_credit_rate(&pods, CAPACITY), 0);
+    }
+
+    #[test]
+    fn processor_without_miners_earns_nothing() {
+        assert_eq!(compute_credit_rate(&processor_with_miners(0), CAPACITY), 0);
+    }
+
+    #[test]
+    fn each_miner_earns_a_credit() {
+        assert_eq!(compute_credit_rate(&processor_with_miners(1), CAPACITY), 1);
+        assert_eq!(compute_credit_rate(&processor_with_miners(3), CAPACITY), 3);
+    }
+
+    #[test]
+    fn miners_beyond_capacity_earn_nothing() {
+        assert_eq!(compute_credit_rate(&processor_with_miners(5), CAPACITY), 3);
+    }
+
+    #[test]
+    fn capacity_is_per_processor() {
+        let mut pods = processor_with_miners(5);
+        pods.push(processor("processor-2", Some("10.0.0.2")));
+        pods.push(miner("miner-other", "10.0.0.2"));
+        assert_eq!(compute_credit_rate(&pods, CAPACITY), 4);
+    }
+
+    #[test]
+    fn miners_targeting_unknown_ips_earn_nothing() {
+        let mut pods = processor_with_miners(1);
+        pods.push(miner("miner-lost", "10.0.0.99"));
+        assert_eq!(compute_credit_rate(&pods, CAPACITY), 1);
+    }
+
+    #[test]
+    fn processor_without_ip_can_not_be_targeted_by_ip() {
+        let pods = [processor("processor-1", None), miner("miner-1", "10.0.0.1")];
+        assert_eq!(compute_credit_rate(&pods, CAPACITY), 0);
+    }
+
+    #[test]
+    fn processor_without_ip_can_be_targeted_by_name() {
+        let pods = [
+            processor("processor-1", None),
+            miner("miner-1", "processor-1"),
+        ];
+        assert_eq!(compute_credit_rate(&pods, CAPACITY), 1);
+    }
+
+    #[test]
+    fn stateful_set_processor_can_be_targeted_by_dns_or_service_name() {
+        let processor = serde_json::from_value(json!({
+            "metadata": {
+                "name": "processor-1-0",
+                "labels": {
+                    "cube-harvest.io/unit-type": "processor",
+                    "cube-harvest.io/unit-name": "processor-1",
+                },
+            },
+            "spec": {
+                "hostname": "processor-1-0",
+                "subdomain": "processor-1",
+                "containers": [{ "name": "astro-unit" }],
+            },
+        }))
+        .unwrap();
+        let pods = [
+            processor,
+            miner("miner-1", "processor-1-0.processor-1"),
+            miner("miner-2", "processor-1.default.svc.cluster.local"),
+        ];
+        assert_eq!(compute_credit_rate(&pods, CAPACITY), 2);
+    }
+
+    #[test]
+    fn upkeep_depends_on_unit_type() {
+        let game_config = GameConfig {
+            miner_upkeep: 1,
+            processor_upkeep: 3,
+            ..Default::default()
+        };
+        assert_eq!(compute_upkeep(&processor_with_miners(2), &game_config), 5);
+    }
+}
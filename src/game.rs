@@ -0,0 +1,1185 @@
+//! The game window and its update/draw loop
+
+use crate::economy::{EconomyClock, EconomyScaling, GameConfig, start_autosave};
+use crate::k8s::{
+    CubeHarvestError, ErrorCategory, GameMessage, GameResources, delete_unit_message,
+    get_unit_name, get_unit_type, valid_miner_target, valid_unit_name,
+};
+use crate::keymap::{Action, KeyMap};
+use crate::render::{
+    Explosions, HitRects, Landings, TextureRegistry, action_error_rect, draw_action_error,
+    draw_alert, draw_build_queue, draw_cluster, draw_cluster_error_banner, draw_command_palette,
+    draw_context_picker, draw_debug_overlay, draw_help, draw_key_hints, draw_loading_screen,
+    draw_logs, draw_minimap, draw_navbar, draw_node, draw_staging_area, draw_target_picker,
+    draw_top_panel, draw_unit_list, unit_position, unit_slot,
+};
+use crate::settings::{MinerTarget, Settings};
+use crate::sound::{Cue, Sounds};
+use crate::state::{
+    Blueprint, BlueprintAction, BuildQueue, Command, CommandPalette, CreateTarget, GameStage,
+    GameState, KubeContexts, ListColumn, MAX_CREATE_COUNT, NavigationMode, NodeSearch, SaveGame,
+    UnitRow, commit_create, flush_pending_miners, run_command, send_action, units_left,
+};
+use core::panic;
+use kube::api::DeleteParams;
+use macroquad::experimental::collections::storage;
+use macroquad::prelude::coroutines::start_coroutine;
+use macroquad::prelude::coroutines::wait_seconds;
+use macroquad::prelude::*;
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+
+/// Time each frame may spend handling messages from the reconciliation loop
+const MESSAGE_BUDGET: Duration = Duration::from_millis(4);
+
+pub fn open_game_window(
+    rx: Receiver<GameMessage>,
+    k_tx: Sender<GameMessage>,
+    settings: Settings,
+    game_config: GameConfig,
+    contexts: KubeContexts,
+) -> JoinHandle<()> {
+    tokio::task::spawn_blocking(|| {
+        macroquad::Window::from_config(
+            Conf {
+                sample_count: 4,
+                window_title: "CubeHarvest: Cluster Frontier".to_string(),
+                high_dpi: true,
+                ..Default::default()
+            },
+            draw(rx, k_tx, settings, game_config, contexts),
+        );
+    })
+}
+
+pub async fn draw(
+    mut rx: Receiver<GameMessage>,
+    k_tx: Sender<GameMessage>,
+    settings: Settings,
+    game_config: GameConfig,
+    contexts: KubeContexts,
+) {
+    rand::srand(miniquad::date::now() as u64);
+    set_pc_assets_folder("assets");
+    storage::store(game_config);
+
+    storage::store(settings.clone());
+    storage::store(KeyMap::new(
+        &settings.keymap,
+        settings.key_repeat_delay_seconds,
+        settings.key_repeat_interval_seconds,
+    ));
+    let save = SaveGame::load();
+    storage::store(GameState {
+        selected_node_index: 0,
+        navigation_mode: NavigationMode::Cluster,
+        create_target: None,
+        create_text_buf: "".to_string(),
+        create_name_buf: String::new(),
+        create_naming: false,
+        create_count: 0,
+        create_target_selection: None,
+        credits: save.credits,
+        miner_price: 0,
+        processor_price: 0,
+        paused: false,
+        cluster_error: None,
+        palette: None,
+        node_search: None,
+        logs: None,
+        pending_blueprint_miners: Vec::new(),
+        credit_deltas: Vec::new(),
+        economy_scale: None,
+        last_earn_rate: 0.,
+        last_consume_rate: 0.,
+        show_all_owners: false,
+        debug_overlay: false,
+        last_update_at: None,
+        broke_for: 0.,
+        should_game_over: false,
+        pending_conversion: None,
+        pending_force_delete: None,
+        pending_delete: None,
+        pending_bulk_delete: None,
+        pending_unknown_target: false,
+        quit_requested: false,
+        alert: None,
+        action_error: None,
+        list_selected: 0,
+        list_sort: ListColumn::Name,
+        list_sort_descending: false,
+        key_hints: false,
+        help: false,
+        unlocked_nodes: save.unlocked_nodes,
+        selected_unit_index: 0,
+        unit_scroll_offset: 0,
+    });
+
+    let mut game_stage = GameStage::MainMenu;
+    let mut coroutines_started = false;
+    // whether the current pause was triggered by `auto_pause_on_error`
+    let mut paused_by_error = false;
+    // `get_time()` of the last keyboard or mouse input, for `idle_sleep_seconds`
+    let mut last_input = get_time();
+    let mut sleeping = false;
+    storage::store(BuildQueue::default());
+    storage::store(contexts);
+    storage::store(HitRects::default());
+    storage::store(TextureRegistry::load().await);
+    // call after loading all textures
+    build_textures_atlas();
+    // particles need a texture of their own, outside the atlas
+    storage::store(Explosions::load().await);
+    storage::store(Landings::default());
+    storage::store(EconomyClock::default());
+    storage::store(Sounds::load().await);
+
+    // game loop
+    loop {
+        // returning from `draw` closes the window
+        if storage::get::<GameState>().quit_requested {
+            break;
+        }
+        clear_background(BLACK);
+
+        // consume messages, leaving the rest for the next frame once the budget
+        // is spent
+        let consume_start = std::time::Instant::now();
+        while consume_start.elapsed() < MESSAGE_BUDGET {
+            match rx.try_recv() {
+                Ok(msg) => match msg {
+                    GameMessage::UpdateResources(game_resources) => {
+                        storage::get_mut::<GameState>().last_update_at = Some(get_time());
+                        if let Some(previous) = storage::try_get::<GameResources>() {
+                            for (name, reason) in &game_resources.misconfigured_miners {
+                                if !previous.misconfigured_miners.contains_key(name) {
+                                    println!("miner {name} is misconfigured: {reason}");
+                                }
+                            }
+                            // the permission can be revoked while playing
+                            if previous.node_view && !game_resources.node_view {
+                                storage::get_mut::<GameState>().alert(
+                                    "You don't have permission to list nodes; node view disabled",
+                                );
+                            }
+                        }
+                        let names = game_resources
+                            .pods
+                            .iter()
+                            .filter_map(|p| p.metadata.name.as_deref())
+                            .collect::<HashSet<_>>();
+                        // deployment pods are queued by their deployment name
+                        let unit_names = game_resources
+                            .pods
+                            .iter()
+                            .filter_map(get_unit_name)
+                            .collect::<HashSet<_>>();
+                        storage::get_mut::<BuildQueue>()
+                            .units
+                            .retain(|u| !unit_names.contains(u.name.as_str()));
+                        // burst where units of the shown node disappeared or
+                        // first appeared
+                        let node_shown = matches!(
+                            storage::get::<GameState>().navigation_mode,
+                            NavigationMode::Node | NavigationMode::Create
+                        );
+                        if node_shown
+                            && let Some(previous) = storage::try_get::<GameResources>()
+                            && let Some(node_name) =
+                                storage::get::<GameState>().selected_node_name()
+                        {
+                            let offset = storage::get::<GameState>().unit_scroll_offset;
+                            let mut explosions = storage::get_mut::<Explosions>();
+                            for (i, p) in previous.pods_on_node(&node_name).iter().enumerate() {
+                                if p.metadata
+                                    .name
+                                    .as_deref()
+                                    .is_some_and(|n| !names.contains(n))
+                                    && let Some(slot) = unit_slot(i, offset)
+                                {
+                                    let is_miner = get_unit_type(p).as_deref() == Some("miner");
+                                    explosions.emitters.spawn(unit_position(slot, is_miner));
+                                }
+                            }
+                            let previous_names = previous
+                                .pods
+                                .iter()
+                                .filter_map(|p| p.metadata.name.as_deref())
+                                .collect::<HashSet<_>>();
+                            for (i, p) in game_resources.pods_on_node(&node_name).iter().enumerate()
+                            {
+                                if p.metadata
+                                    .name
+                                    .as_deref()
+                                    .is_some_and(|n| !previous_names.contains(n))
+                                    && let Some(slot) = unit_slot(i, offset)
+                                {
+                                    let is_miner = get_unit_type(p).as_deref() == Some("miner");
+                                    explosions.spawns.spawn(unit_position(slot, is_miner));
+                                }
+                            }
+                        }
+                        {
+                            let mut game_state = storage::get_mut::<GameState>();
+                            match settings.economy_scaling {
+                                EconomyScaling::Off => {}
+                                EconomyScaling::Session if game_state.economy_scale.is_some() => {}
+                                EconomyScaling::Session | EconomyScaling::Live => {
+                                    game_state.economy_scale =
+                                        Some(EconomyScaling::scale(&game_resources));
+                                }
+                            }
+                        }
+                        // keep the selection on the same node and unit when the
+                        // new snapshot lists them in another order
+                        let selection = storage::try_get::<GameResources>().and_then(|previous| {
+                            let game_state = storage::get::<GameState>();
+                            let node = previous.nodes.get(game_state.selected_node_index)?;
+                            let node_name = node.metadata.name.clone()?;
+                            let unit_name = previous
+                                .pods_on_node(&node_name)
+                                .get(game_state.selected_unit_index)
+                                .and_then(|p| p.metadata.name.clone());
+                            Some((node_name, unit_name))
+                        });
+                        if let Some((node_name, unit_name)) = selection
+                            && let Some(node_index) = game_resources
+                                .nodes
+                                .iter()
+                                .position(|n| n.metadata.name.as_ref() == Some(&node_name))
+                        {
+                            let mut game_state = storage::get_mut::<GameState>();
+                            game_state.selected_node_index = node_index;
+                            if let Some(unit_index) = game_resources
+                                .pods_on_node(&node_name)
+                                .iter()
+                                .position(|p| unit_name.is_some() && p.metadata.name == unit_name)
+                            {
+                                game_state.selected_unit_index = unit_index;
+                            }
+                        }
+                        storage::store(game_resources);
+                    }
+                    GameMessage::CreateFailed(name) => {
+                        storage::get_mut::<BuildQueue>()
+                            .units
+                            .retain(|u| u.name != name);
+                    }
+                    GameMessage::ActionFailed(reason) => {
+                        storage::get_mut::<GameState>().action_error = Some(reason);
+                    }
+                    GameMessage::ConnectionLost(err) => {
+                        storage::get_mut::<GameState>().cluster_error = Some(err.to_string());
+                        if settings.auto_pause_on_error
+                            && err.category() == ErrorCategory::Connectivity
+                            && matches!(game_stage, GameStage::Playing)
+                        {
+                            game_stage = GameStage::Paused;
+                            paused_by_error = true;
+                        }
+                    }
+                    GameMessage::ConnectionRestored => {
+                        storage::get_mut::<GameState>().cluster_error = None;
+                        if settings.auto_resume
+                            && paused_by_error
+                            && matches!(game_stage, GameStage::Paused)
+                        {
+                            game_stage = GameStage::Playing;
+                        }
+                        paused_by_error = false;
+                    }
+                    GameMessage::Logs(logs) => {
+                        // dropped when the overlay was closed in the meantime
+                        if let Some((_, text)) = storage::get_mut::<GameState>().logs.as_mut() {
+                            *text = Some(logs);
+                        }
+                    }
+                    GameMessage::ContextSwitched(name) => {
+                        let mut contexts = storage::get_mut::<KubeContexts>();
+                        contexts.switching = None;
+                        contexts.error = None;
+                        contexts.active = Some(name);
+                    }
+                    GameMessage::ContextSwitchFailed(name, err) => {
+                        let mut contexts = storage::get_mut::<KubeContexts>();
+                        contexts.switching = None;
+                        contexts.error = Some(format!("failed to switch to {name}: {err}"));
+                    }
+                    GameMessage::DeletePod(..)
+                    | GameMessage::CreatePod(_)
+                    | GameMessage::CreateDeployment(_)
+                    | GameMessage::DeleteDeployment(_)
+                    | GameMessage::CreateStatefulSet(..)
+                    | GameMessage::DeleteStatefulSet(_)
+                    | GameMessage::CreateAdvancedUnit(..)
+                    | GameMessage::ConvertUnit(..)
+                    | GameMessage::FetchLogs(_)
+                    | GameMessage::Sleep
+                    | GameMessage::Wake
+                    | GameMessage::SwitchContext(_)
+                    | GameMessage::FilterOwner(_) => unreachable!(),
+                },
+                Err(err) => {
+                    if matches!(err, mpsc::error::TryRecvError::Empty) {
+                        break;
+                    }
+                    panic!("{err}");
+                }
+            }
+        }
+
+        {
+            let mut game_state = storage::get::<GameState>().clone();
+            // nothing to price before the first snapshot arrived
+            if let Some(game_resources) = storage::try_get::<GameResources>() {
+                game_state.miner_price = game_resources.miner_count;
+                game_state.processor_price = game_resources.processor_count;
+            }
+            if game_state.should_game_over {
+                game_state.should_game_over = false;
+                game_state.broke_for = 0.;
+                game_stage = GameStage::GameOver;
+            }
+            game_state.paused = sleeping || !matches!(game_stage, GameStage::Playing);
+            let now = get_time();
+            game_state
+                .credit_deltas
+                .retain(|(_, t)| now - t < settings.credit_delta_seconds);
+            storage::store(game_state);
+        }
+        // the economy only runs while playing, see `GameState::paused`
+        storage::get_mut::<EconomyClock>().update(get_time());
+
+        let has_input = !get_keys_down().is_empty()
+            || !get_keys_pressed().is_empty()
+            || mouse_delta_position() != Vec2::ZERO
+            || is_mouse_button_down(MouseButton::Left)
+            || is_mouse_button_down(MouseButton::Right);
+        if has_input {
+            last_input = get_time();
+        }
+        if let Some(idle_sleep_seconds) = settings.idle_sleep_seconds
+            && !sleeping
+            && get_time() - last_input >= idle_sleep_seconds as f64
+        {
+            sleeping = true;
+            send_action(
+                &mut storage::get_mut::<GameState>(),
+                &k_tx,
+                GameMessage::Sleep,
+                "sleeping",
+            );
+        }
+        if sleeping {
+            if has_input {
+                sleeping = false;
+                send_action(
+                    &mut storage::get_mut::<GameState>(),
+                    &k_tx,
+                    GameMessage::Wake,
+                    "waking up",
+                );
+            } else {
+                storage::get_mut::<GameState>().paused = true;
+                let text = "Sleeping - press any key";
+                let text_dimensions = measure_text(text, None, 40, 1.);
+                draw_text(
+                    text,
+                    screen_width() / 2. - text_dimensions.width / 2.,
+                    screen_height() / 2.,
+                    40.,
+                    GRAY,
+                );
+            }
+            // swallow the input that woke the game up
+            next_frame().await;
+            continue;
+        }
+
+        match game_stage {
+            GameStage::MainMenu => {
+                // update
+                if is_key_pressed(KeyCode::Escape) {
+                    break;
+                }
+
+                {
+                    let mut contexts = storage::get_mut::<KubeContexts>();
+                    let contexts_len = contexts.names.len();
+                    if contexts_len > 0 && contexts.switching.is_none() {
+                        if is_key_pressed(KeyCode::Up) {
+                            contexts.selected =
+                                (contexts.selected + contexts_len - 1) % contexts_len;
+                        }
+                        if is_key_pressed(KeyCode::Down) {
+                            contexts.selected = (contexts.selected + 1) % contexts_len;
+                        }
+                        let name = contexts.names[contexts.selected].clone();
+                        if is_key_pressed(KeyCode::Enter) && contexts.active.as_ref() != Some(&name)
+                        {
+                            let sent = send_action(
+                                &mut storage::get_mut::<GameState>(),
+                                &k_tx,
+                                GameMessage::SwitchContext(name.clone()),
+                                "switching context",
+                            );
+                            if sent {
+                                contexts.switching = Some(name);
+                                contexts.error = None;
+                            }
+                        }
+                    }
+                }
+
+                // wait for the new cluster before playing on the old one
+                let loaded = storage::try_get::<GameResources>().is_some();
+                if is_key_pressed(KeyCode::Space)
+                    && loaded
+                    && storage::get::<KubeContexts>().switching.is_none()
+                {
+                    game_stage = GameStage::Playing;
+                    // the coroutines outlive a game over, they only idle
+                    // while not playing
+                    if !coroutines_started {
+                        coroutines_started = true;
+                        start_autosave();
+                        start_spawn_monkeys(k_tx.clone());
+                    }
+                }
+
+                // draw
+                if !loaded {
+                    draw_loading_screen();
+                    next_frame().await;
+                    continue;
+                }
+                let text = "Press space";
+                let text_dimestions = measure_text(text, None, 50, 1.);
+                draw_text(
+                    text,
+                    screen_width() / 2. - text_dimestions.width / 2.,
+                    screen_height() / 2.,
+                    50.,
+                    WHITE,
+                );
+                draw_context_picker();
+            }
+            GameStage::Playing => {
+                // update
+                let mut game_state = storage::get_mut::<GameState>().clone();
+                let nodes_len = {
+                    let game_resources = storage::get::<GameResources>();
+                    game_resources.nodes.len()
+                };
+
+                let ctrl_down =
+                    is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+                if let Some(reason) = game_state.action_error.as_ref() {
+                    let toast_clicked = is_mouse_button_pressed(MouseButton::Left)
+                        && action_error_rect(reason).contains(mouse_position().into());
+                    if toast_clicked || (is_key_pressed(KeyCode::X) && !game_state.is_typing()) {
+                        game_state.action_error = None;
+                    }
+                }
+                if is_key_pressed(KeyCode::F3) {
+                    game_state.debug_overlay = !game_state.debug_overlay;
+                }
+                if is_key_pressed(KeyCode::Tab) || is_key_pressed(KeyCode::GraveAccent) {
+                    // the economy stops on `GameState::paused`
+                    game_stage = GameStage::Paused;
+                    // swallow the backtick so it doesn't end up in a text field
+                    while get_char_pressed().is_some() {}
+                } else if let Some(name) = game_state.pending_force_delete.clone() {
+                    if is_key_pressed(KeyCode::Y) {
+                        let delete_params = DeleteParams::default().grace_period(0);
+                        send_action(
+                            &mut game_state,
+                            &k_tx,
+                            GameMessage::DeletePod(name, delete_params),
+                            "deleting pod",
+                        );
+                        game_state.pending_force_delete = None;
+                    } else if is_key_pressed(KeyCode::N) || is_key_pressed(KeyCode::Escape) {
+                        game_state.pending_force_delete = None;
+                    }
+                } else if let Some(name) = game_state.pending_delete.clone() {
+                    if is_key_pressed(KeyCode::Y) {
+                        let msg = storage::get::<GameResources>()
+                            .pods
+                            .iter()
+                            .find(|p| p.metadata.name.as_ref() == Some(&name))
+                            .and_then(delete_unit_message);
+                        if let Some(msg) = msg
+                            && send_action(&mut game_state, &k_tx, msg, "deleting pod")
+                        {
+                            storage::get::<Sounds>().play(Cue::Delete);
+                            // the pod is still listed until the next update,
+                            // keep the selection in range once it's gone
+                            if matches!(game_state.navigation_mode, NavigationMode::Node) {
+                                game_state.selected_unit_index = game_state
+                                    .selected_unit_index
+                                    .min(game_state.selected_node_unit_count().saturating_sub(2));
+                            }
+                        }
+                        game_state.pending_delete = None;
+                    } else if is_key_pressed(KeyCode::N) || is_key_pressed(KeyCode::Escape) {
+                        game_state.pending_delete = None;
+                    }
+                } else if let Some(names) = game_state.pending_bulk_delete.clone() {
+                    if is_key_pressed(KeyCode::Y) {
+                        let msgs = storage::get::<GameResources>()
+                            .pods
+                            .iter()
+                            .filter(|p| p.metadata.name.as_ref().is_some_and(|n| names.contains(n)))
+                            .filter_map(delete_unit_message)
+                            .collect::<Vec<_>>();
+                        for msg in msgs {
+                            send_action(&mut game_state, &k_tx, msg, "deleting pod");
+                        }
+                        storage::get::<Sounds>().play(Cue::Delete);
+                        game_state.selected_unit_index = 0;
+                        game_state.unit_scroll_offset = 0;
+                        game_state.pending_bulk_delete = None;
+                    } else if is_key_pressed(KeyCode::N) || is_key_pressed(KeyCode::Escape) {
+                        game_state.pending_bulk_delete = None;
+                    }
+                } else if let Some((name, target, miner_target)) =
+                    game_state.pending_conversion.clone()
+                {
+                    let keymap = storage::get::<KeyMap>();
+                    // a miner needs a target like a created one, typed in
+                    // instead of answering Y/N
+                    let (confirmed, cancelled) = match target {
+                        CreateTarget::Miner => (
+                            keymap.is_action_pressed(Action::Select),
+                            keymap.is_action_pressed(Action::Back),
+                        ),
+                        CreateTarget::Processor => (
+                            is_key_pressed(KeyCode::Y),
+                            is_key_pressed(KeyCode::N) || is_key_pressed(KeyCode::Escape),
+                        ),
+                    };
+                    if confirmed
+                        && matches!(target, CreateTarget::Miner)
+                        && !is_valid_target(&miner_target, settings.miner_target)
+                    {
+                        game_state.alert(format!("Not a valid target: {miner_target:?}"));
+                    } else if confirmed {
+                        send_action(
+                            &mut game_state,
+                            &k_tx,
+                            GameMessage::ConvertUnit(name, target, miner_target),
+                            "converting pod",
+                        );
+                        game_state.pending_conversion = None;
+                    } else if cancelled {
+                        game_state.pending_conversion = None;
+                    } else if let Some((_, CreateTarget::Miner, miner_target)) =
+                        game_state.pending_conversion.as_mut()
+                    {
+                        if is_key_pressed(KeyCode::Backspace) {
+                            miner_target.pop();
+                        }
+                        while let Some(c) = get_char_pressed() {
+                            if c.is_ascii_graphic() {
+                                miner_target.push(c);
+                            }
+                        }
+                    }
+                } else if let Some(mut palette) = game_state.palette.take() {
+                    let commands = palette.matches(&storage::get::<GameResources>());
+                    palette.selected = palette.selected.min(commands.len().saturating_sub(1));
+                    if is_key_pressed(KeyCode::Escape) {
+                        // closed by not putting it back
+                    } else if is_key_pressed(KeyCode::Enter) {
+                        if let Some(command) = commands.get(palette.selected) {
+                            run_command(&mut game_state, command.0, &k_tx);
+                        }
+                    } else {
+                        if is_key_pressed(KeyCode::Down) {
+                            palette.selected = palette.selected.saturating_add(1);
+                        }
+                        if is_key_pressed(KeyCode::Up) {
+                            palette.selected = palette.selected.saturating_sub(1);
+                        }
+                        if is_key_pressed(KeyCode::Backspace) {
+                            palette.query.pop();
+                            palette.selected = 0;
+                        } else if let Some(c) = get_char_pressed()
+                            && !c.is_control()
+                        {
+                            palette.query.push(c);
+                            palette.selected = 0;
+                        }
+                        game_state.palette = Some(palette);
+                    }
+                } else if game_state.help {
+                    if is_key_pressed(KeyCode::Escape) || is_key_pressed(KeyCode::H) {
+                        game_state.help = false;
+                    }
+                } else if is_key_pressed(KeyCode::H) && !game_state.is_typing() {
+                    game_state.help = true;
+                } else if game_state.logs.is_some() {
+                    if is_key_pressed(KeyCode::Escape) || is_key_pressed(KeyCode::L) {
+                        game_state.logs = None;
+                    }
+                } else if let Some(mut search) = game_state.node_search.take() {
+                    if is_key_pressed(KeyCode::Escape) {
+                        game_state.set_selected_node(search.previous_index);
+                    } else if is_key_pressed(KeyCode::Enter) {
+                        // the match is selected already, closed by not putting
+                        // it back
+                    } else {
+                        if is_key_pressed(KeyCode::Backspace) {
+                            search.query.pop();
+                        } else if let Some(c) = get_char_pressed()
+                            && !c.is_control()
+                        {
+                            search.query.push(c);
+                        }
+                        if let Some(index) = search.first_match(&storage::get::<GameResources>()) {
+                            game_state.set_selected_node(index);
+                        }
+                        game_state.node_search = Some(search);
+                    }
+                } else if ctrl_down && is_key_pressed(KeyCode::P) {
+                    // drop the 'p' of the shortcut from the text input queue
+                    while get_char_pressed().is_some() {}
+                    game_state.palette = Some(CommandPalette::default());
+                } else {
+                    // picked against what was drawn last frame
+                    let clicked = is_mouse_button_pressed(MouseButton::Left)
+                        .then(|| Vec2::from(mouse_position()));
+                    // the minimap is drawn over both views, a click on a cell selects
+                    // its node
+                    let minimap_node = clicked
+                        .filter(|_| {
+                            matches!(
+                                game_state.navigation_mode,
+                                NavigationMode::Cluster | NavigationMode::Node
+                            )
+                        })
+                        .and_then(|point| storage::get::<HitRects>().minimap_at(point));
+                    if let Some(node) = minimap_node {
+                        // the node view stays open on the picked node
+                        game_state.set_selected_node(node);
+                    }
+                    let clicked = clicked.filter(|_| minimap_node.is_none());
+                    let mut keymap = storage::get_mut::<KeyMap>();
+                    match game_state.navigation_mode {
+                        NavigationMode::Cluster => {
+                            if let Some(point) = clicked {
+                                let hit_rects = storage::get::<HitRects>();
+                                if let Some(unit) = hit_rects.unit_at(point) {
+                                    run_command(&mut game_state, Command::EnterNode, &k_tx);
+                                    game_state.selected_unit_index = unit;
+                                } else if let Some(node) = hit_rects.node_at(point) {
+                                    // a second click on the selected node enters it
+                                    if node == game_state.selected_node_index {
+                                        run_command(&mut game_state, Command::EnterNode, &k_tx);
+                                    } else {
+                                        run_command(
+                                            &mut game_state,
+                                            Command::JumpToNode(node),
+                                            &k_tx,
+                                        );
+                                    }
+                                }
+                            }
+                            if keymap.is_action_repeated(Action::NavRight) {
+                                run_command(&mut game_state, Command::NextNode, &k_tx);
+                            }
+                            if keymap.is_action_repeated(Action::NavLeft) {
+                                run_command(&mut game_state, Command::PreviousNode, &k_tx);
+                            }
+                            if keymap.is_action_pressed(Action::Select) {
+                                run_command(&mut game_state, Command::EnterNode, &k_tx);
+                            }
+                            if keymap.is_action_pressed(Action::CreateUnit) {
+                                run_command(&mut game_state, Command::CreateUnit, &k_tx);
+                            }
+                            if is_key_pressed(KeyCode::S) {
+                                run_command(&mut game_state, Command::SaveBlueprint, &k_tx);
+                            }
+                            if is_key_pressed(KeyCode::L) {
+                                run_command(&mut game_state, Command::LoadBlueprint, &k_tx);
+                            }
+                            if is_key_pressed(KeyCode::K) {
+                                run_command(&mut game_state, Command::ToggleKeyHints, &k_tx);
+                            }
+                            if is_key_pressed(KeyCode::U) {
+                                run_command(&mut game_state, Command::UnlockNode, &k_tx);
+                            }
+                            if is_key_pressed(KeyCode::V) {
+                                run_command(&mut game_state, Command::ToggleListView, &k_tx);
+                            }
+                            if is_key_pressed(KeyCode::O) {
+                                run_command(&mut game_state, Command::ToggleOwnerFilter, &k_tx);
+                            }
+                            if is_key_pressed(KeyCode::Slash) {
+                                // drop the '/' from the text input queue
+                                while get_char_pressed().is_some() {}
+                                game_state.node_search = Some(NodeSearch {
+                                    query: String::new(),
+                                    previous_index: game_state.selected_node_index,
+                                });
+                            }
+                        }
+                        NavigationMode::List => {
+                            let rows = UnitRow::collect(
+                                &storage::get::<GameResources>(),
+                                game_state.list_sort,
+                                game_state.list_sort_descending,
+                            );
+                            if keymap.is_action_pressed(Action::Back) || is_key_pressed(KeyCode::V)
+                            {
+                                run_command(&mut game_state, Command::ToggleListView, &k_tx);
+                            }
+                            if keymap.is_key_repeated(KeyCode::Down) {
+                                game_state.list_selected =
+                                    game_state.list_selected.saturating_add(1);
+                            }
+                            if keymap.is_key_repeated(KeyCode::Up) {
+                                game_state.list_selected =
+                                    game_state.list_selected.saturating_sub(1);
+                            }
+                            game_state.list_selected =
+                                game_state.list_selected.min(rows.len().saturating_sub(1));
+                            if let Some(c) = get_char_pressed()
+                                && let Some(column) = c
+                                    .to_digit(10)
+                                    .and_then(|d| ListColumn::ALL.get((d as usize).checked_sub(1)?))
+                            {
+                                if game_state.list_sort == *column {
+                                    game_state.list_sort_descending =
+                                        !game_state.list_sort_descending;
+                                } else {
+                                    game_state.list_sort = *column;
+                                    game_state.list_sort_descending = false;
+                                }
+                            }
+                            if let Some(row) = rows.get(game_state.list_selected) {
+                                if keymap.is_action_pressed(Action::Delete) {
+                                    game_state.pending_delete = Some(row.name.clone());
+                                }
+                                // show the unit where it runs
+                                if keymap.is_action_pressed(Action::Select)
+                                    && let Some(index) = storage::get::<GameResources>()
+                                        .nodes
+                                        .iter()
+                                        .position(|n| n.metadata.name.as_ref() == Some(&row.node))
+                                {
+                                    run_command(&mut game_state, Command::JumpToNode(index), &k_tx);
+                                    run_command(&mut game_state, Command::EnterNode, &k_tx);
+                                }
+                            }
+                        }
+                        NavigationMode::Blueprint(action) => {
+                            if keymap.is_action_pressed(Action::Back) {
+                                run_command(&mut game_state, Command::BackToCluster, &k_tx);
+                            } else if keymap.is_action_pressed(Action::Select)
+                                && !game_state.create_text_buf.is_empty()
+                            {
+                                let name = game_state.create_text_buf.clone();
+                                let game_resources = storage::get::<GameResources>();
+                                match action {
+                                    BlueprintAction::Save => {
+                                        match Blueprint::capture(&game_resources).save(&name) {
+                                            Ok(()) => println!("Saved blueprint {name}"),
+                                            Err(err) => {
+                                                println!("failed to save blueprint {name}: {err}")
+                                            }
+                                        }
+                                    }
+                                    BlueprintAction::Load => match Blueprint::load(&name) {
+                                        Ok(blueprint) => {
+                                            let pending = blueprint.apply(&game_resources, &k_tx);
+                                            game_state.pending_blueprint_miners.extend(pending);
+                                            println!("Loaded blueprint {name}");
+                                        }
+                                        Err(err) => {
+                                            println!("failed to load blueprint {name}: {err}")
+                                        }
+                                    },
+                                }
+                                game_state.navigation_mode = NavigationMode::Cluster;
+                            } else if is_key_pressed(KeyCode::Backspace) {
+                                game_state.create_text_buf.pop();
+                            } else if let Some(c) = get_char_pressed()
+                                && (c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                            {
+                                game_state.create_text_buf.push(c);
+                            }
+                        }
+                        NavigationMode::Node => {
+                            if let Some(point) = clicked
+                                && let Some(unit) = storage::get::<HitRects>().unit_at(point)
+                            {
+                                game_state.selected_unit_index = unit;
+                            }
+                            if keymap.is_action_pressed(Action::Back) {
+                                run_command(&mut game_state, Command::BackToCluster, &k_tx);
+                            }
+                            if is_key_pressed(KeyCode::K) {
+                                run_command(&mut game_state, Command::ToggleKeyHints, &k_tx);
+                            }
+
+                            if keymap.is_action_pressed(Action::Delete) {
+                                let shift_down = is_key_down(KeyCode::LeftShift)
+                                    || is_key_down(KeyCode::RightShift);
+                                let command = if shift_down {
+                                    Command::DeleteNodeUnits
+                                } else {
+                                    Command::DeleteUnit
+                                };
+                                run_command(&mut game_state, command, &k_tx);
+                            }
+                            if is_key_pressed(KeyCode::L)
+                                && let Some(name) =
+                                    game_state.selected_unit().and_then(|p| p.metadata.name)
+                                && send_action(
+                                    &mut game_state,
+                                    &k_tx,
+                                    GameMessage::FetchLogs(name.clone()),
+                                    "fetching logs",
+                                )
+                            {
+                                game_state.logs = Some((name, None));
+                            }
+                            if keymap.is_action_repeated(Action::NavRight) {
+                                run_command(&mut game_state, Command::NextUnit, &k_tx);
+                            }
+                            if keymap.is_action_repeated(Action::NavLeft) {
+                                run_command(&mut game_state, Command::PreviousUnit, &k_tx);
+                            }
+                        }
+                        NavigationMode::Create => match &game_state.create_target {
+                            None => {
+                                if keymap.is_action_pressed(Action::Back) {
+                                    run_command(&mut game_state, Command::BackToCluster, &k_tx);
+                                }
+
+                                if keymap.is_action_pressed(Action::SelectMiner) {
+                                    run_command(&mut game_state, Command::BuildMiner, &k_tx);
+                                }
+                                if keymap.is_action_pressed(Action::SelectProcessor) {
+                                    run_command(&mut game_state, Command::BuildProcessor, &k_tx);
+                                }
+                                // a count typed first builds that many units
+                                if is_key_pressed(KeyCode::Backspace) {
+                                    game_state.create_count /= 10;
+                                } else if let Some(c) = get_char_pressed()
+                                    && let Some(digit) = c.to_digit(10)
+                                {
+                                    game_state.create_count = (game_state.create_count * 10
+                                        + digit as usize)
+                                        .min(MAX_CREATE_COUNT);
+                                }
+                                if settings.advanced_unit.is_some() && is_key_pressed(KeyCode::A) {
+                                    run_command(&mut game_state, Command::BuildAdvanced, &k_tx);
+                                }
+                            }
+                            Some(target) => {
+                                // a processor is built right after naming it,
+                                // a miner still needs its target
+                                let named = game_state.create_naming
+                                    && keymap.is_action_pressed(Action::Select);
+                                // the numbered name of the last unit is the
+                                // longest of the batch
+                                let last_name =
+                                    game_state.create_unit_name(game_state.create_batch() - 1);
+                                if named
+                                    && !game_state.create_name_buf.is_empty()
+                                    && !valid_unit_name(&last_name)
+                                {
+                                    game_state.alert(format!(
+                                        "Not a valid name: {last_name:?}, use up to 63 lowercase \
+                                         letters, digits and inner dashes",
+                                    ));
+                                } else if named && matches!(target, CreateTarget::Miner) {
+                                    game_state.create_naming = false;
+                                } else if game_state.create_naming && !named {
+                                    if keymap.is_action_pressed(Action::Back) {
+                                        game_state.navigation_mode = NavigationMode::Cluster;
+                                    } else if is_key_pressed(KeyCode::Backspace) {
+                                        game_state.create_name_buf.pop();
+                                    } else if let Some(c) = get_char_pressed()
+                                        && (c.is_ascii_alphanumeric() || c == '-')
+                                    {
+                                        game_state.create_name_buf.push(c.to_ascii_lowercase());
+                                    }
+                                } else if game_state.pending_unknown_target
+                                    && !is_key_pressed(KeyCode::Y)
+                                {
+                                    // keep the answer out of the target text
+                                    while get_char_pressed().is_some() {}
+                                    if is_key_pressed(KeyCode::N)
+                                        || keymap.is_action_pressed(Action::Back)
+                                    {
+                                        game_state.pending_unknown_target = false;
+                                    }
+                                } else if keymap.is_action_pressed(Action::Select)
+                                    || game_state.pending_unknown_target
+                                {
+                                    // still pending here means confirmed with Y
+                                    let confirmed =
+                                        std::mem::take(&mut game_state.pending_unknown_target);
+                                    let has_enough_credit =
+                                        game_state.credits >= game_state.create_price(target);
+
+                                    let node_name = game_state.selected_node_name();
+                                    let node_locked = settings.node_budget
+                                        && !node_name.as_ref().is_some_and(|name| {
+                                            game_state.is_node_unlocked(
+                                                game_state.selected_node_index,
+                                                name,
+                                            )
+                                        });
+
+                                    let target_valid = match target {
+                                        CreateTarget::Miner => is_valid_target(
+                                            &game_state.create_text_buf,
+                                            settings.miner_target,
+                                        ),
+                                        CreateTarget::Processor => true,
+                                    };
+
+                                    let target_known = confirmed
+                                        || matches!(target, CreateTarget::Processor)
+                                        || storage::get::<GameResources>()
+                                            .has_processor_at(&game_state.create_text_buf);
+
+                                    if !target_valid {
+                                        game_state.alert(format!(
+                                            "Not a valid target: {:?}",
+                                            game_state.create_text_buf
+                                        ));
+                                    } else if units_left()
+                                        .is_some_and(|left| game_state.create_batch() > left)
+                                    {
+                                        let max_units = settings.max_units.unwrap_or_default();
+                                        game_state.alert(
+                                            CubeHarvestError::UnitLimit(max_units).to_string(),
+                                        );
+                                    } else if !target_known {
+                                        game_state.pending_unknown_target = true;
+                                    } else if node_locked {
+                                        game_state.alert("Unlock the node before building on it");
+                                    } else if has_enough_credit {
+                                        let target = target.clone();
+                                        match commit_create(
+                                            &mut game_state,
+                                            &target,
+                                            node_name,
+                                            &k_tx,
+                                        ) {
+                                            Ok(()) => storage::get::<Sounds>().play(Cue::Create),
+                                            Err(err) => println!("{err}"),
+                                        }
+                                    } else {
+                                        storage::get::<Sounds>().play(Cue::Denied);
+                                        let price = game_state.create_price(target);
+                                        let batch = game_state.create_batch();
+                                        game_state.alert(if batch > 1 {
+                                            format!(
+                                                "Not enough credits for {batch} {}s (${price})",
+                                                target.unit_type()
+                                            )
+                                        } else {
+                                            format!(
+                                                "Not enough credits for a {} (${price})",
+                                                target.unit_type()
+                                            )
+                                        });
+                                    }
+
+                                    // let the player fix a mistyped target
+                                    if target_valid && !game_state.pending_unknown_target {
+                                        game_state.navigation_mode = NavigationMode::Cluster;
+                                    }
+                                } else if keymap.is_action_pressed(Action::Back) {
+                                    game_state.navigation_mode = NavigationMode::Cluster;
+                                } else if matches!(target, CreateTarget::Miner)
+                                    && (is_key_pressed(KeyCode::Up)
+                                        || is_key_pressed(KeyCode::Down))
+                                {
+                                    let game_resources = storage::get::<GameResources>();
+                                    let processors = game_resources.processors();
+                                    if !processors.is_empty() {
+                                        let i = match game_state.create_target_selection {
+                                            None => 0,
+                                            Some(i) if is_key_pressed(KeyCode::Up) => {
+                                                i.saturating_sub(1)
+                                            }
+                                            Some(i) => i.saturating_add(1),
+                                        }
+                                        .min(processors.len() - 1);
+                                        game_state.create_target_selection = Some(i);
+                                        game_state.create_text_buf =
+                                            processors[i].target(settings.miner_target).to_string();
+                                    }
+                                } else if is_key_pressed(KeyCode::Backspace) {
+                                    game_state.create_text_buf.pop();
+                                    game_state.create_target_selection = None;
+                                } else if let Some(c) = get_char_pressed()
+                                    && (c.is_ascii_digit()
+                                        || c == '.'
+                                        || (settings.miner_target == MinerTarget::Name
+                                            && (c.is_ascii_lowercase() || c == '-')))
+                                {
+                                    game_state.create_text_buf.push(c);
+                                    game_state.create_target_selection = None;
+                                }
+                            }
+                        },
+                    }
+                }
+
+                flush_pending_miners(&mut game_state, &k_tx);
+
+                let index = clamp(
+                    game_state.selected_node_index,
+                    0,
+                    nodes_len.saturating_sub(1),
+                );
+                game_state.set_selected_node(index);
+                game_state.selected_unit_index = game_state
+                    .selected_unit_index
+                    .min(game_state.selected_node_unit_count().saturating_sub(1));
+                game_state.scroll_to_selected_unit();
+                // post update
+                storage::store(game_state);
+
+                draw_top_panel();
+                if matches!(
+                    storage::get::<GameState>().navigation_mode,
+                    NavigationMode::List
+                ) {
+                    draw_unit_list();
+                } else if matches!(
+                    storage::get::<GameState>().navigation_mode,
+                    NavigationMode::Cluster | NavigationMode::Blueprint(_)
+                ) {
+                    draw_cluster();
+                    draw_staging_area();
+                    draw_minimap();
+                } else {
+                    draw_node();
+                    draw_staging_area();
+                    draw_minimap();
+                    let mut explosions = storage::get_mut::<Explosions>();
+                    explosions.emitters.draw();
+                    explosions.spawns.draw();
+                }
+                draw_key_hints();
+                draw_navbar();
+                draw_build_queue();
+                draw_alert();
+                draw_action_error();
+                draw_target_picker();
+                draw_command_palette();
+                draw_logs();
+                draw_help();
+                draw_debug_overlay(rx.len());
+            }
+            GameStage::Paused => {
+                if is_key_pressed(KeyCode::Space)
+                    || is_key_pressed(KeyCode::Tab)
+                    || is_key_pressed(KeyCode::GraveAccent)
+                {
+                    game_stage = GameStage::Playing;
+                    paused_by_error = false;
+                }
+
+                draw_top_panel();
+
+                let text = "Paused";
+                let text_dimensions = measure_text(text, None, 50, 1.);
+                draw_text(
+                    text,
+                    screen_width() / 2. - text_dimensions.width / 2.,
+                    screen_height() / 2.,
+                    50.,
+                    WHITE,
+                );
+
+                if paused_by_error {
+                    draw_cluster_error_banner();
+                }
+            }
+            GameStage::GameOver => {
+                if is_key_pressed(KeyCode::Space) {
+                    game_stage = GameStage::MainMenu;
+                }
+
+                let text = "GAME OVER!";
+                let text_dimensions = measure_text(text, None, 50, 1.);
+                draw_text(
+                    text,
+                    screen_width() / 2. - text_dimensions.width / 2.,
+                    screen_height() / 2.,
+                    50.,
+                    RED,
+                );
+                let hint = "Out of credits to keep your units running - press space";
+                let hint_dimensions = measure_text(hint, None, 24, 1.);
+                draw_text(
+                    hint,
+                    screen_width() / 2. - hint_dimensions.width / 2.,
+                    screen_height() / 2. + 40.,
+                    24.,
+                    GRAY,
+                );
+            }
+        };
+
+        next_frame().await
+    }
+    SaveGame::of(&storage::get::<GameState>()).save();
+}
+
+/// A miner can store `target` as its TARGET, well-formed or the stable name of
+/// a processor
+fn is_valid_target(target: &str, mode: MinerTarget) -> bool {
+    valid_miner_target(target, mode)
+        || storage::get::<GameResources>()
+            .processors()
+            .iter()
+            .any(|p| p.stable_name.as_deref() == Some(target))
+}
+
+fn start_spawn_monkeys(k_tx: Sender<GameMessage>) {
+    start_coroutine(async move {
+        loop {
+            // spare the units while paused, over or asleep
+            if !storage::get::<GameState>().paused && rand::gen_range(0, 100) > 95 {
+                println!("starting deleting pod");
+                let game_resources = storage::get::<GameResources>();
+                if !game_resources.pods.is_empty() {
+                    let i = rand::gen_range(0, game_resources.pods.len());
+                    match game_resources.pods[i].metadata.name.as_ref() {
+                        Some(pod_name) => {
+                            send_action(
+                                &mut storage::get_mut::<GameState>(),
+                                &k_tx,
+                                GameMessage::DeletePod(
+                                    pod_name.to_string(),
+                                    DeleteParams::default(),
+                                ),
+                                "deleting pod",
+                            );
+                        }
+                        None => {
+                            println!("pod {i} does not have name");
+                        }
+                    };
+                }
+            }
+            wait_seconds(3.).await;
+        }
+    });
+}
@@ -0,0 +1,1653 @@
+//! Cluster snapshots, unit manifests and the actions applied to the cluster
+
+use crate::economy::{GameConfig, compute_credit_rate, earning_miners};
+use crate::settings::MinerTarget;
+use crate::state::CreateTarget;
+use askama::Template;
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use k8s_openapi::api::apps::v1::Deployment;
+use k8s_openapi::api::apps::v1::StatefulSet;
+use k8s_openapi::api::core::v1::Node;
+use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::api::core::v1::ResourceQuota;
+use k8s_openapi::api::core::v1::Service;
+use k8s_openapi::chrono::{DateTime, Utc};
+use kube::api::ApiResource;
+use kube::api::DeleteParams;
+use kube::api::DynamicObject;
+use kube::api::LogParams;
+use kube::api::Patch;
+use kube::api::PatchParams;
+use kube::api::PostParams;
+use kube::config::{KubeConfigOptions, Kubeconfig};
+use kube::runtime::WatchStreamExt;
+use kube::runtime::watcher;
+use kube::{Api, Client, Config, api::ListParams};
+use macroquad::prelude::*;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::Sender;
+
+/// Overrides the TARGET env of a unit, pod env can't be changed after creation
+pub const TARGET_ANNOTATION: &str = "cube-harvest.io/target";
+/// Player name of the units created with `--owner`, to tell players apart on
+/// a shared cluster
+pub const OWNER_LABEL: &str = "cube-harvest.io/owner";
+/// Identifies the units created by this run of the game
+pub static SESSION_ID: LazyLock<String> = LazyLock::new(|| uuid::Uuid::new_v4().to_string());
+const LIST_PAGE_SIZE: u32 = 500;
+pub const LOG_TAIL_LINES: i64 = 50;
+/// Pods still around this long after their deletion timestamp count as stuck
+/// and can be force deleted
+const STUCK_TERMINATING_AFTER: Duration = Duration::from_secs(30);
+/// Creation timestamps further in the future than this count as clock skew
+const CLOCK_SKEW_TOLERANCE: Duration = Duration::from_secs(30);
+
+#[derive(Template, Debug)]
+#[template(path = "astro-unit.json", escape = "none")]
+struct AstroUnitTemplate {
+    name: String,
+    /// IP or name of the processor a miner sends its resources to
+    miner_target: String,
+    unit_type: String,
+    session_id: String,
+    /// Resource requests of the container, so units spread over the nodes and
+    /// a node can fill up
+    cpu_request: String,
+    mem_request: String,
+    image: String,
+    /// Player name of `--owner`, the unit is left unowned without one
+    owner: Option<String>,
+}
+
+/// Single replica Deployment around a unit, its pod spec is taken from the
+/// rendered [`AstroUnitTemplate`]
+#[derive(Template, Debug)]
+#[template(path = "astro-unit-deployment.json", escape = "none")]
+struct AstroUnitDeploymentTemplate {
+    name: String,
+    unit_type: String,
+    session_id: String,
+    owner: Option<String>,
+}
+
+/// Single replica StatefulSet around a processor, its pod spec is taken from
+/// the rendered [`AstroUnitTemplate`]
+#[derive(Template, Debug)]
+#[template(path = "astro-unit-statefulset.json", escape = "none")]
+struct AstroUnitStatefulSetTemplate {
+    name: String,
+    unit_type: String,
+    session_id: String,
+    owner: Option<String>,
+}
+
+/// Headless Service of the same name as an [`AstroUnitStatefulSetTemplate`],
+/// giving its pod a DNS name that survives restarts
+#[derive(Template, Debug)]
+#[template(path = "astro-unit-service.json", escape = "none")]
+struct AstroUnitServiceTemplate {
+    name: String,
+    unit_type: String,
+    session_id: String,
+    owner: Option<String>,
+}
+
+/// Snapshot of the cluster. Everything derived from the pod list is computed
+/// when the snapshot is built in the reconciliation task, so the render thread
+/// only reads it.
+pub struct GameResources {
+    pub pods: Vec<Pod>,
+    pub nodes: Vec<Node>,
+    pub miner_count: usize,
+    pub processor_count: usize,
+    /// Miners by the target they send their resources to, see [`target_key`]
+    miners_per_target: HashMap<String, usize>,
+    /// Credits earned per tick, see [`compute_credit_rate`]
+    pub credit_rate: usize,
+    /// `GameConfig::processor_capacity` the snapshot was computed with
+    pub processor_capacity: usize,
+    /// Indices into `pods`, grouped by the name of the node they run on
+    pods_by_node: HashMap<String, Vec<usize>>,
+    /// Names of miners without exactly one well-formed TARGET, with the reason
+    pub misconfigured_miners: HashMap<String, String>,
+    /// Most pods were created in the future of the local clock
+    pub clock_skewed: bool,
+    /// `nodes` come from the node API, otherwise they are stand-ins without
+    /// capacity so node load and other node features are hidden
+    pub node_view: bool,
+}
+
+impl GameResources {
+    pub async fn new(
+        client: &Client,
+        namespace: &str,
+        owner: Option<&str>,
+        processor_capacity: usize,
+    ) -> Result<Self, kube::Error> {
+        let pods = list_pods(client, namespace, owner).await?;
+        let nodes = list_nodes(client).await?;
+
+        Ok(Self::from_items(pods, nodes, processor_capacity))
+    }
+
+    /// `nodes` is `None` without permission to list nodes, see [`list_nodes`]
+    pub fn from_items(pods: Vec<Pod>, nodes: Option<Vec<Node>>, processor_capacity: usize) -> Self {
+        let node_view = nodes.is_some();
+        let nodes = nodes.unwrap_or_else(|| nodes_from_pods(&pods));
+        let count_type = |unit_type: &str| {
+            pods.iter()
+                .filter(|p| get_unit_type(p).as_deref() == Some(unit_type))
+                .count()
+        };
+        let miner_count = count_type("miner");
+        let processor_count = count_type("processor");
+
+        let mut pods_by_node = HashMap::<String, Vec<usize>>::new();
+        for (i, p) in pods.iter().enumerate() {
+            if let Some(node_name) = p.spec.as_ref().and_then(|s| s.node_name.clone()) {
+                pods_by_node.entry(node_name).or_default().push(i);
+            }
+        }
+
+        let misconfigured_miners = pods
+            .iter()
+            .filter(|p| get_unit_type(p).as_deref() == Some("miner"))
+            .filter_map(|p| Some((p.metadata.name.clone()?, validate_miner(p).err()?)))
+            .collect();
+
+        // a single odd timestamp is noise, skew shows up across many pods
+        let now = Utc::now();
+        let created_in_future = pods
+            .iter()
+            .filter_map(|p| p.metadata.creation_timestamp.as_ref())
+            .filter(|t| (t.0 - now).to_std().is_ok_and(|d| d > CLOCK_SKEW_TOLERANCE))
+            .count();
+        let clock_skewed = created_in_future >= 3 && created_in_future * 2 > pods.len();
+
+        let credit_rate = compute_credit_rate(&pods, processor_capacity);
+        let mut miners_per_target = HashMap::<String, usize>::new();
+        for p in &pods {
+            if get_unit_type(p).as_deref() == Some("miner")
+                && let Some(target) = get_unit_target(p)
+            {
+                *miners_per_target
+                    .entry(target_key(&target).to_string())
+                    .or_default() += 1;
+            }
+        }
+
+        Self {
+            pods,
+            nodes,
+            miner_count,
+            processor_count,
+            miners_per_target,
+            credit_rate,
+            processor_capacity,
+            pods_by_node,
+            misconfigured_miners,
+            clock_skewed,
+            node_view,
+        }
+    }
+
+    /// Processors that have an IP, in pod order, with the number of miners
+    /// targeting them
+    pub fn processors(&self) -> Vec<ProcessorInfo<'_>> {
+        self.pods
+            .iter()
+            .filter(|p| get_unit_type(p).as_deref() == Some("processor"))
+            .filter_map(|p| {
+                let ips = unit_ips(p);
+                let name = p.metadata.name.as_deref().unwrap_or("<unnamed>");
+                Some(ProcessorInfo {
+                    name,
+                    stable_name: stable_dns_name(p),
+                    ip: ips.first()?,
+                    miners: self.miners_targeting(p),
+                    capacity: self.processor_capacity,
+                    ips,
+                })
+            })
+            .collect()
+    }
+
+    /// Miners targeting the processor `p` by any of its IPs or names, they
+    /// may exceed `processor_capacity`
+    pub fn miners_targeting(&self, p: &Pod) -> usize {
+        processor_targets(p)
+            .into_iter()
+            .filter_map(|target| self.miners_per_target.get(target))
+            .sum()
+    }
+
+    /// Pods that are game units, the ones `Settings::max_units` counts
+    pub fn unit_count(&self) -> usize {
+        self.pods
+            .iter()
+            .filter(|p| get_unit_type(p).is_some())
+            .count()
+    }
+
+    /// Whether a miner with this TARGET would reach a processor, like
+    /// [`earning_miners`] matches them
+    pub fn has_processor_at(&self, target: &str) -> bool {
+        let key = target_key(target);
+        self.pods
+            .iter()
+            .filter(|p| get_unit_type(p).as_deref() == Some("processor"))
+            .any(|p| processor_targets(p).contains(&key))
+    }
+
+    /// See [`earning_miners`]
+    pub fn earning_miners(&self) -> HashMap<&str, usize> {
+        earning_miners(&self.pods, self.processor_capacity)
+    }
+
+    /// Pods scheduled on the node named `node_name`
+    pub fn pods_on_node(&self, node_name: &str) -> Vec<&Pod> {
+        self.pods_by_node
+            .get(node_name)
+            .map(|indices| indices.iter().map(|&i| &self.pods[i]).collect())
+            .unwrap_or_default()
+    }
+
+    /// Pods the scheduler hasn't placed on a node yet
+    pub fn unscheduled_pods(&self) -> Vec<&Pod> {
+        self.pods
+            .iter()
+            .filter(|p| p.spec.as_ref().is_none_or(|s| s.node_name.is_none()))
+            .collect()
+    }
+}
+
+pub struct ProcessorInfo<'a> {
+    pub name: &'a str,
+    /// DNS name of a StatefulSet processor, see [`stable_dns_name`]
+    pub stable_name: Option<String>,
+    /// Primary IP, the one picked as a miner target
+    pub ip: &'a str,
+    /// All IPs assigned to the processor, including `ip`
+    pub ips: Vec<&'a str>,
+    /// Miners targeting this processor, may exceed `capacity`
+    pub miners: usize,
+    pub capacity: usize,
+}
+
+impl ProcessorInfo<'_> {
+    pub fn free_capacity(&self) -> usize {
+        self.capacity.saturating_sub(self.miners)
+    }
+
+    /// What a new miner stores as its TARGET to reach this processor, the
+    /// stable DNS name whenever it has one
+    pub fn target(&self, mode: MinerTarget) -> &str {
+        if let Some(stable_name) = self.stable_name.as_deref() {
+            return stable_name;
+        }
+        match mode {
+            MinerTarget::Ip => self.ip,
+            MinerTarget::Name => self.name,
+        }
+    }
+}
+
+/// Cap the pods of the namespace at `max_units` with a ResourceQuota named
+/// `cube-harvest`, applied so a quota left by an earlier run is updated
+pub async fn apply_pod_quota(
+    client: &Client,
+    namespace: &str,
+    max_units: usize,
+) -> Result<(), CubeHarvestError> {
+    let api = Api::<ResourceQuota>::namespaced(client.clone(), namespace);
+    let quota = serde_json::json!({
+        "apiVersion": "v1",
+        "kind": "ResourceQuota",
+        "metadata": { "name": "cube-harvest" },
+        "spec": { "hard": { "pods": max_units.to_string() } },
+    });
+    api.patch(
+        "cube-harvest",
+        &PatchParams::apply("cube-harvest"),
+        &Patch::Apply(&quota),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Build a client from the kubeconfig and check the apiserver answers, using
+/// the named context or inferring the config when `None`
+pub async fn connect(context: Option<&str>) -> Result<Client, CubeHarvestError> {
+    let config = match context {
+        Some(context) => {
+            let options = KubeConfigOptions {
+                context: Some(context.to_string()),
+                ..Default::default()
+            };
+            Config::from_kubeconfig(&options).await?
+        }
+        None => Config::infer().await?,
+    };
+    let client = Client::try_from(config)?;
+    client.apiserver_version().await?;
+    Ok(client)
+}
+
+/// Context names in the kubeconfig along with its current context, empty when
+/// there is no kubeconfig, e.g. running in-cluster
+pub fn kube_contexts() -> (Vec<String>, Option<String>) {
+    match Kubeconfig::read() {
+        Ok(kubeconfig) => (
+            kubeconfig.contexts.into_iter().map(|c| c.name).collect(),
+            kubeconfig.current_context,
+        ),
+        Err(err) => {
+            println!("failed to read kubeconfig contexts: {err}");
+            (Vec::new(), None)
+        }
+    }
+}
+
+/// Exponential delay between reconnection attempts
+pub struct Backoff {
+    delay: Duration,
+}
+
+impl Backoff {
+    const INITIAL: Duration = Duration::from_secs(1);
+    const MAX: Duration = Duration::from_secs(30);
+
+    pub fn new() -> Self {
+        Self {
+            delay: Self::INITIAL,
+        }
+    }
+
+    /// The delay to wait now, doubling the following one
+    pub fn next(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(Self::MAX);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.delay = Self::INITIAL;
+    }
+}
+
+pub type WatchStream<K> = BoxStream<'static, Result<watcher::Event<K>, watcher::Error>>;
+
+/// Label selector of the units of `owner`, see [`OWNER_LABEL`]
+fn owner_selector(owner: &str) -> String {
+    format!("{OWNER_LABEL}={owner}")
+}
+
+/// Only the pods of `owner` when given, every pod of the namespace otherwise
+pub fn watch_pods(client: &Client, namespace: &str, owner: Option<&str>) -> WatchStream<Pod> {
+    let mut config = watcher::Config::default();
+    if let Some(owner) = owner {
+        config = config.labels(&owner_selector(owner));
+    }
+    watcher(Api::namespaced(client.clone(), namespace), config)
+        .default_backoff()
+        .boxed()
+}
+
+pub fn watch_nodes(client: &Client) -> WatchStream<Node> {
+    watcher(Api::all(client.clone()), watcher::Config::default())
+        .default_backoff()
+        .boxed()
+}
+
+async fn list_pods(
+    client: &Client,
+    namespace: &str,
+    owner: Option<&str>,
+) -> Result<Vec<Pod>, kube::Error> {
+    let mut list_params = ListParams::default();
+    if let Some(owner) = owner {
+        list_params = list_params.labels(&owner_selector(owner));
+    }
+    list_all(&Api::namespaced(client.clone(), namespace), list_params).await
+}
+
+/// `None` when listing nodes is forbidden, it needs cluster-scoped permissions
+/// that namespaced users often lack
+async fn list_nodes(client: &Client) -> Result<Option<Vec<Node>>, kube::Error> {
+    match list_all(&Api::all(client.clone()), ListParams::default()).await {
+        Ok(nodes) => Ok(Some(nodes)),
+        Err(kube::Error::Api(response)) if response.code == 403 => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Stand-in nodes named after the `spec.node_name` of the pods, without any
+/// node metadata
+fn nodes_from_pods(pods: &[Pod]) -> Vec<Node> {
+    let names = pods
+        .iter()
+        .filter_map(|p| p.spec.as_ref()?.node_name.clone())
+        .collect::<std::collections::BTreeSet<_>>();
+    names
+        .into_iter()
+        .map(|name| Node {
+            metadata: kube::api::ObjectMeta {
+                name: Some(name),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Errors surfaced to the player, grouped by [`ErrorCategory`] so the game can
+/// react to them differently
+#[derive(Debug)]
+pub enum CubeHarvestError {
+    Kube(kube::Error),
+    /// Rendering the unit manifest template failed
+    Template(askama::Error),
+    /// The rendered unit manifest is not a valid pod
+    Json(serde_json::Error),
+    /// The other end of a game channel is gone
+    Channel,
+    /// Reading a manifest from disk failed
+    Io(std::io::Error),
+    /// A watch failed without a cluster error
+    Watch(watcher::Error),
+    /// No usable kubeconfig or in-cluster config was found
+    Config(kube::config::InferConfigError),
+    /// The kubeconfig can't be read or lacks the chosen context
+    Kubeconfig(kube::config::KubeconfigError),
+    /// A message the cluster backend has no action for, e.g. one only the
+    /// reconciliation loop handles
+    NotAnAction,
+    /// Creating another unit would exceed `Settings::max_units`
+    UnitLimit(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    /// The cluster can't be reached, retrying later may succeed
+    Connectivity,
+    /// The cluster refused the request for the current credentials
+    Permission,
+    /// The request itself is invalid
+    Validation,
+    Internal,
+}
+
+impl CubeHarvestError {
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            Self::Kube(kube::Error::Api(response)) => match response.code {
+                401 | 403 => ErrorCategory::Permission,
+                400 | 404 | 409 | 422 => ErrorCategory::Validation,
+                _ => ErrorCategory::Connectivity,
+            },
+            Self::Kube(_) | Self::Config(_) | Self::Kubeconfig(_) => ErrorCategory::Connectivity,
+            Self::Template(_) | Self::Json(_) | Self::Io(_) | Self::UnitLimit(_) => {
+                ErrorCategory::Validation
+            }
+            Self::Channel | Self::Watch(_) | Self::NotAnAction => ErrorCategory::Internal,
+        }
+    }
+}
+
+impl std::fmt::Display for CubeHarvestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Kube(kube::Error::Api(response)) => match self.category() {
+                ErrorCategory::Permission => write!(f, "permission denied: {}", response.message),
+                _ => write!(f, "cluster rejected the request: {}", response.message),
+            },
+            Self::Kube(err) => write!(f, "cluster unreachable: {err}"),
+            Self::Template(err) => write!(f, "failed to render unit manifest: {err}"),
+            Self::Json(err) => write!(f, "invalid unit manifest: {err}"),
+            Self::Channel => write!(f, "game channel closed"),
+            Self::Io(err) => write!(f, "failed to read unit manifest: {err}"),
+            Self::Watch(err) => write!(f, "{err}"),
+            Self::Config(err) => write!(f, "failed to load kubeconfig: {err}"),
+            Self::Kubeconfig(err) => write!(f, "failed to load kubeconfig: {err}"),
+            Self::NotAnAction => write!(f, "not a cluster action"),
+            Self::UnitLimit(max_units) => {
+                write!(
+                    f,
+                    "Unit limit reached ({max_units} units), delete some first"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for CubeHarvestError {}
+
+impl From<kube::config::InferConfigError> for CubeHarvestError {
+    fn from(err: kube::config::InferConfigError) -> Self {
+        Self::Config(err)
+    }
+}
+
+impl From<kube::config::KubeconfigError> for CubeHarvestError {
+    fn from(err: kube::config::KubeconfigError) -> Self {
+        Self::Kubeconfig(err)
+    }
+}
+
+impl From<kube::Error> for CubeHarvestError {
+    fn from(err: kube::Error) -> Self {
+        Self::Kube(err)
+    }
+}
+
+impl From<askama::Error> for CubeHarvestError {
+    fn from(err: askama::Error) -> Self {
+        Self::Template(err)
+    }
+}
+
+impl From<watcher::Error> for CubeHarvestError {
+    fn from(err: watcher::Error) -> Self {
+        match err {
+            watcher::Error::InitialListFailed(err)
+            | watcher::Error::WatchStartFailed(err)
+            | watcher::Error::WatchFailed(err) => Self::Kube(err),
+            watcher::Error::WatchError(response) => Self::Kube(kube::Error::Api(response)),
+            err => Self::Watch(err),
+        }
+    }
+}
+
+impl From<std::io::Error> for CubeHarvestError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for CubeHarvestError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+impl<T> From<mpsc::error::SendError<T>> for CubeHarvestError {
+    fn from(_: mpsc::error::SendError<T>) -> Self {
+        Self::Channel
+    }
+}
+
+/// List every object of `api`, following continue tokens so that large
+/// namespaces are fetched in pages of [`LIST_PAGE_SIZE`]
+async fn list_all<K>(api: &Api<K>, list_params: ListParams) -> Result<Vec<K>, kube::Error>
+where
+    K: Clone + DeserializeOwned + std::fmt::Debug,
+{
+    let mut items = Vec::new();
+    let mut list_params = list_params.limit(LIST_PAGE_SIZE);
+    loop {
+        let list = api.list(&list_params).await?;
+        items.extend(list.items);
+        match list.metadata.continue_ {
+            Some(token) if !token.is_empty() => list_params = list_params.continue_token(&token),
+            _ => break,
+        }
+    }
+    Ok(items)
+}
+
+/// Objects of a watched API by name, kept up to date from watcher events
+pub struct WatchCache<K> {
+    pub items: BTreeMap<String, K>,
+    /// Objects received since the watcher (re)started listing, replacing
+    /// `items` once the list is complete
+    init: Option<BTreeMap<String, K>>,
+}
+
+impl<K> Default for WatchCache<K> {
+    fn default() -> Self {
+        Self {
+            items: BTreeMap::new(),
+            init: None,
+        }
+    }
+}
+
+impl<K: kube::Resource> WatchCache<K> {
+    /// Returns whether `items` changed
+    pub fn apply(&mut self, event: watcher::Event<K>) -> bool {
+        let name = |object: &K| object.meta().name.clone().unwrap_or_default();
+        match event {
+            watcher::Event::Apply(object) => {
+                self.items.insert(name(&object), object);
+                true
+            }
+            watcher::Event::Delete(object) => self.items.remove(&name(&object)).is_some(),
+            watcher::Event::Init => {
+                self.init = Some(BTreeMap::new());
+                false
+            }
+            watcher::Event::InitApply(object) => {
+                self.init
+                    .get_or_insert_default()
+                    .insert(name(&object), object);
+                false
+            }
+            watcher::Event::InitDone => {
+                self.items = self.init.take().unwrap_or_default();
+                true
+            }
+        }
+    }
+}
+
+/// Pods the API accepted, waiting to show up in a pod listing
+pub struct PendingCreations {
+    /// Off when `None`
+    timeout: Option<Duration>,
+    deadlines: HashMap<String, std::time::Instant>,
+}
+
+impl PendingCreations {
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            deadlines: HashMap::new(),
+        }
+    }
+
+    fn track(&mut self, name: String) {
+        if let Some(timeout) = self.timeout {
+            self.deadlines
+                .insert(name, std::time::Instant::now() + timeout);
+        }
+    }
+
+    /// The pod showed up in the cluster
+    pub fn seen(&mut self, name: &str) {
+        self.deadlines.remove(name);
+    }
+
+    /// Forget and return the pods whose deadline passed without showing up,
+    /// e.g. removed by an admission webhook or a policy controller right after
+    /// the create call succeeded
+    pub fn vanished(&mut self) -> Vec<String> {
+        let now = std::time::Instant::now();
+        let vanished: Vec<_> = self
+            .deadlines
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in &vanished {
+            self.deadlines.remove(name);
+        }
+        vanished
+    }
+}
+
+/// Where the actions of the game are applied, see [`KubeBackend`] and
+/// [`crate::simulate::SimulatedCluster`]
+pub trait ClusterBackend {
+    /// Apply an action sent by the game, replying over `tx` for the actions
+    /// expecting an answer
+    async fn apply(
+        &mut self,
+        msg: GameMessage,
+        tx: &Sender<GameMessage>,
+    ) -> Result<(), CubeHarvestError>;
+}
+
+/// The cluster of the kubeconfig, followed by the reconciliation loop in `main`
+pub struct KubeBackend {
+    pub client: Client,
+    pub namespace: String,
+    pub pending_creations: PendingCreations,
+}
+
+impl ClusterBackend for KubeBackend {
+    async fn apply(
+        &mut self,
+        msg: GameMessage,
+        tx: &Sender<GameMessage>,
+    ) -> Result<(), CubeHarvestError> {
+        handle_cluster_action(
+            &self.client,
+            &self.namespace,
+            msg,
+            tx,
+            &mut self.pending_creations,
+        )
+        .await
+    }
+}
+
+/// Apply an action requested by the game to the cluster
+pub async fn handle_cluster_action(
+    client: &Client,
+    namespace: &str,
+    msg: GameMessage,
+    tx: &Sender<GameMessage>,
+    pending_creations: &mut PendingCreations,
+) -> Result<(), CubeHarvestError> {
+    match msg {
+        GameMessage::CreatePod(pod) => {
+            let api = Api::namespaced(client.clone(), namespace);
+            create_pod(&api, &pod, tx, pending_creations).await?;
+        }
+        GameMessage::CreateDeployment(deployment) => {
+            let api = Api::<Deployment>::namespaced(client.clone(), namespace);
+            match api.create(&PostParams::default(), &*deployment).await {
+                Ok(created) => {
+                    if let Some(name) = created.metadata.name {
+                        pending_creations.track(name);
+                    }
+                }
+                Err(err) => {
+                    let name = deployment.metadata.name.clone().unwrap_or_default();
+                    tx.send(GameMessage::CreateFailed(name)).await?;
+                    return Err(err.into());
+                }
+            }
+        }
+        GameMessage::FetchLogs(name) => {
+            let api = Api::<Pod>::namespaced(client.clone(), namespace);
+            let params = LogParams {
+                tail_lines: Some(LOG_TAIL_LINES),
+                ..Default::default()
+            };
+            // shown in place of the logs, the player is looking at the overlay
+            let logs = match api.logs(&name, &params).await {
+                Ok(logs) => logs,
+                Err(err) => format!("failed to fetch logs: {}", CubeHarvestError::from(err)),
+            };
+            tx.send(GameMessage::Logs(logs)).await?;
+        }
+        GameMessage::DeleteDeployment(name) => {
+            let api = Api::<Deployment>::namespaced(client.clone(), namespace);
+            api.delete(&name, &DeleteParams::default()).await?;
+        }
+        GameMessage::CreateStatefulSet(stateful_set, service) => {
+            let name = stateful_set.metadata.name.clone().unwrap_or_default();
+            let services = Api::<Service>::namespaced(client.clone(), namespace);
+            let stateful_sets = Api::<StatefulSet>::namespaced(client.clone(), namespace);
+            let created = match services.create(&PostParams::default(), &*service).await {
+                Ok(_) => {
+                    let created = stateful_sets
+                        .create(&PostParams::default(), &*stateful_set)
+                        .await;
+                    // don't leave the Service of a StatefulSet that never came
+                    if created.is_err() {
+                        let deleted = services.delete(&name, &DeleteParams::default()).await;
+                        if let Err(err) = ignore_not_found(deleted) {
+                            println!("failed to delete the service of {name}: {err}");
+                        }
+                    }
+                    created
+                }
+                Err(err) => Err(err),
+            };
+            match created {
+                Ok(_) => pending_creations.track(name),
+                Err(err) => {
+                    tx.send(GameMessage::CreateFailed(name)).await?;
+                    return Err(err.into());
+                }
+            }
+        }
+        GameMessage::DeleteStatefulSet(name) => {
+            let stateful_sets = Api::<StatefulSet>::namespaced(client.clone(), namespace);
+            let services = Api::<Service>::namespaced(client.clone(), namespace);
+            // either may be gone already, the other is still deleted
+            let stateful_set_deleted =
+                ignore_not_found(stateful_sets.delete(&name, &DeleteParams::default()).await);
+            let service_deleted =
+                ignore_not_found(services.delete(&name, &DeleteParams::default()).await);
+            stateful_set_deleted?;
+            service_deleted?;
+        }
+        GameMessage::CreateAdvancedUnit(object, api_resource) => {
+            let api =
+                Api::<DynamicObject>::namespaced_with(client.clone(), namespace, &api_resource);
+            api.create(&PostParams::default(), &*object).await?;
+        }
+        GameMessage::DeletePod(name, delete_params) => {
+            let api = Api::namespaced(client.clone(), namespace);
+            delete_pod(&api, &name, &delete_params).await?;
+        }
+        GameMessage::ConvertUnit(name, target, miner_target) => {
+            let api = Api::<Pod>::namespaced(client.clone(), namespace);
+            // the TARGET env can't be changed on a running pod, so it's
+            // overridden through the annotation instead
+            let patch = serde_json::json!({
+                "metadata": {
+                    "labels": { "cube-harvest.io/unit-type": target.unit_type() },
+                    "annotations": { TARGET_ANNOTATION: miner_target },
+                },
+            });
+            api.patch(&name, &PatchParams::default(), &Patch::Merge(&patch))
+                .await?;
+        }
+        GameMessage::UpdateResources(_)
+        | GameMessage::CreateFailed(_)
+        | GameMessage::ActionFailed(_)
+        | GameMessage::ConnectionLost(_)
+        | GameMessage::ConnectionRestored
+        | GameMessage::Sleep
+        | GameMessage::Wake
+        | GameMessage::Logs(_)
+        | GameMessage::SwitchContext(_)
+        | GameMessage::FilterOwner(_)
+        | GameMessage::ContextSwitched(_)
+        | GameMessage::ContextSwitchFailed(..) => return Err(CubeHarvestError::NotAnAction),
+    }
+    Ok(())
+}
+
+/// A delete of an object that is already gone succeeded
+fn ignore_not_found<T>(result: Result<T, kube::Error>) -> Result<(), kube::Error> {
+    match result {
+        Err(kube::Error::Api(response)) if response.code == 404 => Ok(()),
+        result => result.map(|_| ()),
+    }
+}
+
+/// Create a unit as a bare pod and wait for it to show up, telling the game
+/// when the API rejected it
+pub async fn create_pod(
+    api: &Api<Pod>,
+    pod: &Pod,
+    tx: &Sender<GameMessage>,
+    pending_creations: &mut PendingCreations,
+) -> Result<(), CubeHarvestError> {
+    match api.create(&PostParams::default(), pod).await {
+        Ok(created) => {
+            if let Some(name) = created.metadata.name {
+                pending_creations.track(name);
+            }
+            Ok(())
+        }
+        Err(err) => {
+            let name = pod.metadata.name.clone().unwrap_or_default();
+            tx.send(GameMessage::CreateFailed(name)).await?;
+            Err(err.into())
+        }
+    }
+}
+
+pub async fn delete_pod(
+    api: &Api<Pod>,
+    name: &str,
+    delete_params: &DeleteParams,
+) -> Result<(), CubeHarvestError> {
+    api.delete(name, delete_params).await?;
+    Ok(())
+}
+
+pub enum GameMessage {
+    UpdateResources(GameResources),
+    CreatePod(Box<Pod>),
+    /// Create a unit as a Deployment, see [`crate::settings::UnitWorkload::Deployment`]
+    CreateDeployment(Box<Deployment>),
+    /// Delete a unit created as a Deployment along with its pod
+    DeleteDeployment(String),
+    /// Create a processor as a StatefulSet with its headless Service, see
+    /// `Settings::stateful_processors`
+    CreateStatefulSet(Box<StatefulSet>, Box<Service>),
+    /// Delete a unit created as a StatefulSet along with its Service
+    DeleteStatefulSet(String),
+    /// Create a custom resource through the dynamic API
+    CreateAdvancedUnit(Box<DynamicObject>, ApiResource),
+    /// Delete pod by name
+    DeletePod(String, DeleteParams),
+    /// Change the unit type of the named pod, with the TARGET of a miner or
+    /// blank for a processor
+    ConvertUnit(String, CreateTarget, String),
+    /// Fetch the last [`LOG_TAIL_LINES`] log lines of the named pod
+    FetchLogs(String),
+    /// Answer to `FetchLogs`, or why they couldn't be fetched
+    Logs(String),
+    /// The game went idle, stop polling the cluster until `Wake`
+    Sleep,
+    Wake,
+    /// The reconciliation loop failed to reach the cluster
+    ConnectionLost(CubeHarvestError),
+    /// The cluster is reachable again after a `ConnectionLost`
+    ConnectionRestored,
+    /// The named pod was rejected or never showed up after being created
+    CreateFailed(String),
+    /// An action sent to the reconciliation loop failed, with the reason
+    ActionFailed(String),
+    /// Follow the cluster of the named kubeconfig context from now on
+    SwitchContext(String),
+    /// The reconciliation loop switched to the named context
+    ContextSwitched(String),
+    /// Switching to a context failed, the loop stays on the previous one
+    ContextSwitchFailed(String, CubeHarvestError),
+    /// Follow only the units of the given owner from now on, or every unit
+    /// with `None`
+    FilterOwner(Option<String>),
+}
+
+pub fn build_unit(
+    name: String,
+    target: &CreateTarget,
+    miner_target: String,
+    owner: Option<&str>,
+    game_config: &GameConfig,
+) -> Result<Pod, CubeHarvestError> {
+    let astro_unit = AstroUnitTemplate {
+        name,
+        miner_target,
+        unit_type: target.unit_type().to_string(),
+        session_id: SESSION_ID.clone(),
+        cpu_request: game_config.cpu_request.clone(),
+        mem_request: game_config.mem_request.clone(),
+        image: match target {
+            CreateTarget::Miner => game_config.miner_image.clone(),
+            CreateTarget::Processor => game_config.processor_image.clone(),
+        },
+        owner: owner.map(str::to_string),
+    }
+    .render()?;
+    Ok(serde_json::from_str::<Pod>(&astro_unit)?)
+}
+
+/// Wrap a unit built by [`build_unit`] in a Deployment of the same name
+pub fn deployment_for(pod: Pod) -> Result<Deployment, CubeHarvestError> {
+    let deployment = AstroUnitDeploymentTemplate {
+        name: pod.metadata.name.clone().unwrap_or_default(),
+        unit_type: get_unit_type(&pod).unwrap_or_default(),
+        session_id: SESSION_ID.clone(),
+        owner: get_owner(&pod).map(str::to_string),
+    }
+    .render()?;
+    let mut deployment = serde_json::from_str::<Deployment>(&deployment)?;
+    if let Some(spec) = deployment.spec.as_mut() {
+        spec.template.spec = pod.spec;
+    }
+    Ok(deployment)
+}
+
+/// Wrap a processor built by [`build_unit`] in a StatefulSet of the same name
+/// and the headless Service its pod is reachable through
+pub fn stateful_set_for(pod: Pod) -> Result<(StatefulSet, Service), CubeHarvestError> {
+    let name = pod.metadata.name.clone().unwrap_or_default();
+    let unit_type = get_unit_type(&pod).unwrap_or_default();
+    let owner = get_owner(&pod).map(str::to_string);
+    let stateful_set = AstroUnitStatefulSetTemplate {
+        name: name.clone(),
+        unit_type: unit_type.clone(),
+        session_id: SESSION_ID.clone(),
+        owner: owner.clone(),
+    }
+    .render()?;
+    let mut stateful_set = serde_json::from_str::<StatefulSet>(&stateful_set)?;
+    if let Some(spec) = stateful_set.spec.as_mut() {
+        spec.template.spec = pod.spec;
+    }
+    let service = AstroUnitServiceTemplate {
+        name,
+        unit_type,
+        session_id: SESSION_ID.clone(),
+        owner,
+    }
+    .render()?;
+    Ok((stateful_set, serde_json::from_str::<Service>(&service)?))
+}
+
+/// Delete what the player sees as the unit, the Deployment or StatefulSet of
+/// their pods since the pod alone would just be replaced
+pub fn delete_unit_message(pod: &Pod) -> Option<GameMessage> {
+    let name = pod.metadata.name.clone()?;
+    match get_unit_name(pod) {
+        Some(unit_name) if unit_name != name && is_stateful_set_pod(pod) => {
+            Some(GameMessage::DeleteStatefulSet(unit_name.to_string()))
+        }
+        Some(unit_name) if unit_name != name => {
+            Some(GameMessage::DeleteDeployment(unit_name.to_string()))
+        }
+        _ => Some(GameMessage::DeletePod(name, DeleteParams::default())),
+    }
+}
+
+/// Whether the pod belongs to a Deployment or StatefulSet unit, whose pod
+/// template would undo changes to the pod itself
+pub fn is_workload_pod(p: &Pod) -> bool {
+    get_unit_name(p).is_some_and(|unit_name| p.metadata.name.as_deref() != Some(unit_name))
+}
+
+fn is_stateful_set_pod(p: &Pod) -> bool {
+    p.metadata
+        .owner_references
+        .iter()
+        .flatten()
+        .any(|o| o.kind == "StatefulSet")
+}
+
+/// `<hostname>.<subdomain>` of a pod behind a headless Service, e.g.
+/// `processor-1-0.processor-1` for a StatefulSet pod. It survives the pod
+/// restarting with a new IP.
+pub fn stable_dns_name(p: &Pod) -> Option<String> {
+    let spec = p.spec.as_ref()?;
+    Some(format!(
+        "{}.{}",
+        spec.hostname.as_ref()?,
+        spec.subdomain.as_ref()?
+    ))
+}
+
+/// Everything a miner TARGET can refer to the processor `p` by, its IPs, its
+/// pod name and the name of the Service or workload it belongs to, see
+/// [`target_key`]
+pub fn processor_targets(p: &Pod) -> Vec<&str> {
+    let mut targets = unit_ips(p);
+    targets.extend(p.metadata.name.as_deref());
+    if let Some(unit_name) = get_unit_name(p)
+        && !targets.contains(&unit_name)
+    {
+        targets.push(unit_name);
+    }
+    targets
+}
+
+pub fn get_unit_ip(p: &Pod) -> Option<&str> {
+    p.status.as_ref().and_then(|s| s.pod_ip.as_deref())
+}
+
+/// Check that a miner has exactly one TARGET holding an IP address or a
+/// processor name, either through [`TARGET_ANNOTATION`] or the env of its
+/// containers
+fn validate_miner(p: &Pod) -> Result<(), String> {
+    let annotation = p
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|a| a.get(TARGET_ANNOTATION));
+    let targets = match annotation {
+        Some(target) => vec![Some(target.as_str())],
+        None => p
+            .spec
+            .iter()
+            .flat_map(|s| &s.containers)
+            .flat_map(|c| c.env.iter().flatten())
+            .filter(|e| e.name == "TARGET")
+            .map(|e| e.value.as_deref())
+            .collect(),
+    };
+    match targets.as_slice() {
+        [] => Err("no TARGET".to_string()),
+        [Some(target)] if target.parse::<std::net::IpAddr>().is_ok() || is_dns_name(target) => {
+            Ok(())
+        }
+        [Some(target)] => Err(format!(
+            "TARGET {target:?} is not an IP address or processor name"
+        )),
+        [None] => Err("TARGET has no value".to_string()),
+        _ => Err(format!("{} TARGET entries", targets.len())),
+    }
+}
+
+/// All IPs of a unit, `status.pod_ip` first followed by any other address in
+/// `status.pod_ips` (e.g. the IPv6 one of a dual-stack pod)
+pub fn unit_ips(p: &Pod) -> Vec<&str> {
+    let mut ips = Vec::new();
+    if let Some(ip) = get_unit_ip(p) {
+        ips.push(ip);
+    }
+    let pod_ips = p.status.as_ref().and_then(|s| s.pod_ips.as_ref());
+    for ip in pod_ips.into_iter().flatten() {
+        if !ips.contains(&ip.ip.as_str()) {
+            ips.push(&ip.ip);
+        }
+    }
+    ips
+}
+
+/// `target` is something a miner can be pointed at in the given mode
+pub fn valid_miner_target(target: &str, mode: MinerTarget) -> bool {
+    match mode {
+        MinerTarget::Ip => Ipv4Addr::from_str(target).is_ok(),
+        MinerTarget::Name => is_dns_name(target),
+    }
+}
+
+/// `name` is a valid RFC 1123 label, i.e. can name a pod and its unit
+pub fn valid_unit_name(name: &str) -> bool {
+    !name.contains('.') && is_dns_name(name)
+}
+
+/// Lowercase alphanumeric labels separated by dots, with dashes inside labels
+fn is_dns_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        })
+}
+
+/// Part of a miner TARGET that identifies the processor: IPs as is, and the
+/// first label of DNS names, e.g. `processor-1` for
+/// `processor-1.cube-harvest.default.svc.cluster.local`
+pub fn target_key(target: &str) -> &str {
+    if target.parse::<std::net::IpAddr>().is_ok() {
+        return target;
+    }
+    target.split('.').next().unwrap_or(target)
+}
+
+/// The processor IP or name a miner sends its resources to
+pub fn get_unit_target(p: &Pod) -> Option<String> {
+    if let Some(target) = p
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|a| a.get(TARGET_ANNOTATION))
+    {
+        return Some(target.clone());
+    }
+    get_env_target(p)
+}
+
+/// TARGET the unit was created with, before any [`TARGET_ANNOTATION`]
+pub fn get_env_target(p: &Pod) -> Option<String> {
+    p.spec
+        .as_ref()
+        .and_then(|s| s.containers[0].env.as_ref())
+        .and_then(|e| e.iter().find(|e| e.name == "TARGET"))
+        .and_then(|e| e.value.clone())
+}
+
+/// Parse a Kubernetes quantity string (e.g. `"500m"`, `"2Gi"`, `"1e3"`) into
+/// its numeric value in base units
+pub fn parse_quantity(q: &str) -> Option<f64> {
+    let q = q.trim();
+    let suffixes = [
+        ("Ki", 1024f64),
+        ("Mi", 1024f64.powi(2)),
+        ("Gi", 1024f64.powi(3)),
+        ("Ti", 1024f64.powi(4)),
+        ("Pi", 1024f64.powi(5)),
+        ("Ei", 1024f64.powi(6)),
+        ("n", 1e-9),
+        ("u", 1e-6),
+        ("m", 1e-3),
+        ("k", 1e3),
+        ("M", 1e6),
+        ("G", 1e9),
+        ("T", 1e12),
+        ("P", 1e15),
+        ("E", 1e18),
+    ];
+    for (suffix, multiplier) in suffixes {
+        if let Some(number) = q.strip_suffix(suffix) {
+            return number.parse::<f64>().ok().map(|n| n * multiplier);
+        }
+    }
+    q.parse::<f64>().ok()
+}
+
+/// Sum of the `resource` requests of all containers in `p`
+pub fn pod_request(p: &Pod, resource: &str) -> f64 {
+    p.spec
+        .as_ref()
+        .map(|s| {
+            s.containers
+                .iter()
+                .filter_map(|c| c.resources.as_ref()?.requests.as_ref()?.get(resource))
+                .filter_map(|q| parse_quantity(&q.0))
+                .sum()
+        })
+        .unwrap_or(0.)
+}
+
+/// How close the summed requests of `pods` are to the allocatable capacity of
+/// `node`, as the highest ratio among cpu and memory. Returns `None` if the
+/// node doesn't report allocatable resources.
+pub fn node_load(node: &Node, pods: &[&Pod]) -> Option<f32> {
+    let allocatable = node.status.as_ref()?.allocatable.as_ref()?;
+    ["cpu", "memory"]
+        .into_iter()
+        .filter_map(|resource| {
+            let capacity = parse_quantity(&allocatable.get(resource)?.0)?;
+            if capacity <= 0. {
+                return None;
+            }
+            let requested = pods.iter().map(|p| pod_request(p, resource)).sum::<f64>();
+            Some((requested / capacity) as f32)
+        })
+        .reduce(f32::max)
+}
+
+/// Name of the unit a pod belongs to, the Deployment name for deployment pods
+pub fn get_unit_name(p: &Pod) -> Option<&str> {
+    p.metadata
+        .labels
+        .as_ref()
+        .and_then(|l| l.get("cube-harvest.io/unit-name"))
+        .or(p.metadata.name.as_ref())
+        .map(String::as_str)
+}
+
+pub fn get_unit_type(p: &Pod) -> Option<String> {
+    p.metadata
+        .labels
+        .as_ref()
+        .and_then(|l| l.get("cube-harvest.io/unit-type").cloned())
+}
+
+/// Player who created the unit, the [`OWNER_LABEL`] set with `--owner`
+pub fn get_owner(p: &Pod) -> Option<&str> {
+    p.metadata
+        .labels
+        .as_ref()
+        .and_then(|l| l.get(OWNER_LABEL))
+        .map(String::as_str)
+}
+
+/// ID of the game session which created the unit
+pub fn get_session_id(p: &Pod) -> Option<&str> {
+    p.metadata
+        .labels
+        .as_ref()
+        .and_then(|l| l.get("cube-harvest.io/session-id"))
+        .map(String::as_str)
+}
+
+/// Whether the scheduler found no node with room for the pod
+pub fn is_unschedulable(pod: &Pod) -> bool {
+    pod.status
+        .as_ref()
+        .and_then(|s| s.conditions.as_ref())
+        .is_some_and(|conditions| {
+            conditions.iter().any(|c| {
+                c.type_ == "PodScheduled"
+                    && c.status == "False"
+                    && c.reason.as_deref() == Some("Unschedulable")
+            })
+        })
+}
+
+/// Whether the pod is still terminating [`STUCK_TERMINATING_AFTER`] past its
+/// deletion timestamp, e.g. held by a finalizer or an unreachable node
+pub fn is_stuck_terminating(pod: &Pod, now: DateTime<Utc>) -> bool {
+    pod.metadata.deletion_timestamp.as_ref().is_some_and(|t| {
+        (now - t.0)
+            .to_std()
+            .is_ok_and(|d| d > STUCK_TERMINATING_AFTER)
+    })
+}
+
+/// Whether a container can't start since its image doesn't pull, e.g. a
+/// registry unreachable from the cluster
+pub fn is_image_pull_failing(p: &Pod) -> bool {
+    p.status
+        .as_ref()
+        .and_then(|s| s.container_statuses.as_ref())
+        .into_iter()
+        .flatten()
+        .filter_map(|s| s.state.as_ref()?.waiting.as_ref()?.reason.as_deref())
+        .any(|reason| matches!(reason, "ImagePullBackOff" | "ErrImagePull"))
+}
+
+/// Restarts of all containers of the pod, a unit that keeps crashing
+/// restarts over and over
+pub fn restart_count(p: &Pod) -> i32 {
+    p.status
+        .as_ref()
+        .and_then(|s| s.container_statuses.as_ref())
+        .map(|statuses| statuses.iter().map(|s| s.restart_count).sum())
+        .unwrap_or(0)
+}
+
+/// Time since the pod was created, zero if the local clock is behind the
+/// cluster
+pub fn unit_age(pod: &Pod, now: DateTime<Utc>) -> Option<Duration> {
+    let created = pod.metadata.creation_timestamp.as_ref()?;
+    Some((now - created.0).to_std().unwrap_or(Duration::ZERO))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::{Method, Request, Response};
+    use kube::client::Body;
+    use std::sync::{Arc, Mutex};
+
+    /// Method, path and body of a request the fake apiserver got
+    type Recorded = Arc<Mutex<Vec<(Method, String, serde_json::Value)>>>;
+
+    /// Client of an apiserver answering every request with `response`
+    fn fake_apiserver(response: serde_json::Value) -> (Client, Recorded) {
+        routed_apiserver(move |_, _| (200, response.clone()))
+    }
+
+    /// Client of an apiserver answering with the status code and body
+    /// `respond` picks by method and path
+    fn routed_apiserver(
+        respond: impl Fn(&Method, &str) -> (u16, serde_json::Value) + Send + Sync + 'static,
+    ) -> (Client, Recorded) {
+        let recorded = Recorded::default();
+        let requests = recorded.clone();
+        let respond = Arc::new(respond);
+        let service = tower::service_fn(move |request: Request<Body>| {
+            let requests = requests.clone();
+            let respond = respond.clone();
+            async move {
+                let (parts, body) = request.into_parts();
+                let body = body.collect_bytes().await?;
+                let body = serde_json::from_slice(&body).unwrap_or_default();
+                let path = parts.uri.path().to_string();
+                let (status, response) = respond(&parts.method, &path);
+                requests.lock().unwrap().push((parts.method, path, body));
+                let response = Response::builder()
+                    .status(status)
+                    .body(Body::from(serde_json::to_vec(&response).unwrap()))
+                    .unwrap();
+                Ok::<_, kube::Error>(response)
+            }
+        });
+        (Client::new(service, "game"), recorded)
+    }
+
+    fn miner() -> Pod {
+        build_unit(
+            "miner-1".to_string(),
+            &CreateTarget::Miner,
+            "10.0.0.1".to_string(),
+            None,
+            &GameConfig::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn built_miner_is_labeled_and_targets_its_processor() {
+        let pod = miner();
+        assert_eq!(pod.metadata.name.as_deref(), Some("miner-1"));
+        assert_eq!(get_unit_type(&pod).as_deref(), Some("miner"));
+        assert_eq!(get_session_id(&pod), Some(SESSION_ID.as_str()));
+        assert_eq!(get_unit_target(&pod).as_deref(), Some("10.0.0.1"));
+        assert_eq!(get_owner(&pod), None);
+    }
+
+    #[test]
+    fn built_processor_is_labeled() {
+        let pod = build_unit(
+            "processor-1".to_string(),
+            &CreateTarget::Processor,
+            String::new(),
+            Some("alice"),
+            &GameConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(get_unit_type(&pod).as_deref(), Some("processor"));
+        assert_eq!(get_owner(&pod), Some("alice"));
+    }
+
+    #[test]
+    fn miner_targets_are_matched_against_processors() {
+        let mut processor = build_unit(
+            "processor-1".to_string(),
+            &CreateTarget::Processor,
+            String::new(),
+            None,
+            &GameConfig::default(),
+        )
+        .unwrap();
+        processor.status = Some(k8s_openapi::api::core::v1::PodStatus {
+            pod_ip: Some("10.0.0.1".to_string()),
+            ..Default::default()
+        });
+        let game_resources = GameResources::from_items(vec![processor, miner()], None, 3);
+        assert!(game_resources.has_processor_at("10.0.0.1"));
+        assert!(game_resources.has_processor_at("processor-1"));
+        assert!(!game_resources.has_processor_at("10.0.0.2"));
+    }
+
+    #[test]
+    fn restarts_are_summed_over_containers() {
+        let mut pod = miner();
+        assert_eq!(restart_count(&pod), 0);
+        pod.status = Some(
+            serde_json::from_value(serde_json::json!({
+                "containerStatuses": [
+                    { "name": "a", "image": "a", "imageID": "", "ready": false, "restartCount": 4 },
+                    { "name": "b", "image": "b", "imageID": "", "ready": true, "restartCount": 1 },
+                ],
+            }))
+            .unwrap(),
+        );
+        assert_eq!(restart_count(&pod), 5);
+    }
+
+    #[test]
+    fn units_use_the_image_of_their_type() {
+        let game_config = GameConfig {
+            processor_image: "mirror.internal/processor:1".to_string(),
+            ..Default::default()
+        };
+        let pod = build_unit(
+            "processor-1".to_string(),
+            &CreateTarget::Processor,
+            String::new(),
+            None,
+            &game_config,
+        )
+        .unwrap();
+        let image = pod.spec.unwrap().containers[0].image.clone();
+        assert_eq!(image.as_deref(), Some("mirror.internal/processor:1"));
+    }
+
+    #[test]
+    fn image_pull_errors_are_detected() {
+        let mut pod = miner();
+        assert!(!is_image_pull_failing(&pod));
+        pod.status = Some(
+            serde_json::from_value(serde_json::json!({
+                "containerStatuses": [{
+                    "name": "astro-unit",
+                    "image": "registry.cube-harvest.invalid/miner:42",
+                    "imageID": "",
+                    "ready": false,
+                    "restartCount": 0,
+                    "state": { "waiting": { "reason": "ImagePullBackOff" } },
+                }],
+            }))
+            .unwrap(),
+        );
+        assert!(is_image_pull_failing(&pod));
+    }
+
+    #[tokio::test]
+    async fn create_pod_posts_to_the_namespace() {
+        let pod = miner();
+        let (client, recorded) = fake_apiserver(serde_json::to_value(&pod).unwrap());
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let mut pending_creations = PendingCreations::new(Some(Duration::ZERO));
+
+        create_pod(
+            &Api::namespaced(client, "game"),
+            &pod,
+            &tx,
+            &mut pending_creations,
+        )
+        .await
+        .unwrap();
+
+        let recorded = recorded.lock().unwrap();
+        let [(method, path, body)] = recorded.as_slice() else {
+            panic!("expected a single request, got {recorded:?}");
+        };
+        assert_eq!(method, Method::POST);
+        assert_eq!(path, "/api/v1/namespaces/game/pods");
+        assert_eq!(
+            body["metadata"]["labels"]["cube-harvest.io/unit-type"],
+            "miner"
+        );
+        assert_eq!(body["spec"]["containers"][0]["env"][0]["name"], "TARGET");
+        assert_eq!(body["spec"]["containers"][0]["env"][0]["value"], "10.0.0.1");
+        // created pods are expected to show up in the listing
+        assert_eq!(pending_creations.vanished(), ["miner-1"]);
+    }
+
+    #[tokio::test]
+    async fn delete_pod_deletes_by_name() {
+        let (client, recorded) = fake_apiserver(serde_json::to_value(miner()).unwrap());
+
+        delete_pod(
+            &Api::namespaced(client, "game"),
+            "miner-1",
+            &DeleteParams::default(),
+        )
+        .await
+        .unwrap();
+
+        let recorded = recorded.lock().unwrap();
+        let [(method, path, _)] = recorded.as_slice() else {
+            panic!("expected a single request, got {recorded:?}");
+        };
+        assert_eq!(method, Method::DELETE);
+        assert_eq!(path, "/api/v1/namespaces/game/pods/miner-1");
+    }
+
+    /// Body of an apiserver error response
+    fn status(code: u16, reason: &str) -> serde_json::Value {
+        serde_json::json!({
+            "kind": "Status",
+            "apiVersion": "v1",
+            "status": "Failure",
+            "message": reason,
+            "reason": reason,
+            "code": code,
+        })
+    }
+
+    fn stateful_processor() -> (StatefulSet, Service) {
+        let pod = build_unit(
+            "processor-1".to_string(),
+            &CreateTarget::Processor,
+            String::new(),
+            None,
+            &GameConfig::default(),
+        )
+        .unwrap();
+        stateful_set_for(pod).unwrap()
+    }
+
+    #[tokio::test]
+    async fn failed_stateful_set_create_deletes_its_service() {
+        let (stateful_set, service) = stateful_processor();
+        let created_service = serde_json::to_value(&service).unwrap();
+        let (client, recorded) = routed_apiserver(move |method, path| {
+            match (method.as_str(), path.ends_with("/statefulsets")) {
+                ("POST", true) => (422, status(422, "Invalid")),
+                _ => (200, created_service.clone()),
+            }
+        });
+        let (tx, _rx) = mpsc::channel(1);
+        let msg = GameMessage::CreateStatefulSet(Box::new(stateful_set), Box::new(service));
+
+        handle_cluster_action(&client, "game", msg, &tx, &mut PendingCreations::new(None))
+            .await
+            .unwrap_err();
+
+        let recorded = recorded.lock().unwrap();
+        let requests = recorded
+            .iter()
+            .map(|(method, path, _)| (method.as_str(), path.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            requests,
+            [
+                ("POST", "/api/v1/namespaces/game/services"),
+                ("POST", "/apis/apps/v1/namespaces/game/statefulsets"),
+                ("DELETE", "/api/v1/namespaces/game/services/processor-1"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn deleting_a_gone_stateful_set_still_deletes_its_service() {
+        let (_, service) = stateful_processor();
+        let deleted_service = serde_json::to_value(&service).unwrap();
+        let (client, recorded) = routed_apiserver(move |_, path| {
+            if path.contains("/statefulsets/") {
+                (404, status(404, "NotFound"))
+            } else {
+                (200, deleted_service.clone())
+            }
+        });
+        let (tx, _rx) = mpsc::channel(1);
+        let msg = GameMessage::DeleteStatefulSet("processor-1".to_string());
+
+        handle_cluster_action(&client, "game", msg, &tx, &mut PendingCreations::new(None))
+            .await
+            .unwrap();
+
+        let recorded = recorded.lock().unwrap();
+        assert_eq!(recorded.len(), 2);
+        assert_eq!(recorded[1].0, Method::DELETE);
+        assert_eq!(
+            recorded[1].1,
+            "/api/v1/namespaces/game/services/processor-1"
+        );
+    }
+
+    #[test]
+    fn only_pods_of_workloads_are_workload_pods() {
+        let pod = miner();
+        assert!(!is_workload_pod(&pod));
+        let deployment_pod: Pod = serde_json::from_value(serde_json::json!({
+            "metadata": {
+                "name": "miner-1-5d8f7c9b4-x2kqp",
+                "labels": { "cube-harvest.io/unit-name": "miner-1" },
+            },
+        }))
+        .unwrap();
+        assert!(is_workload_pod(&deployment_pod));
+    }
+
+    #[tokio::test]
+    async fn converted_miner_gets_its_target() {
+        let (client, recorded) = fake_apiserver(serde_json::to_value(miner()).unwrap());
+        let (tx, _rx) = mpsc::channel(1);
+        let msg = GameMessage::ConvertUnit(
+            "processor-1".to_string(),
+            CreateTarget::Miner,
+            "10.0.0.2".to_string(),
+        );
+
+        handle_cluster_action(&client, "game", msg, &tx, &mut PendingCreations::new(None))
+            .await
+            .unwrap();
+
+        let recorded = recorded.lock().unwrap();
+        let [(method, path, body)] = recorded.as_slice() else {
+            panic!("expected a single request, got {recorded:?}");
+        };
+        assert_eq!(method, Method::PATCH);
+        assert_eq!(path, "/api/v1/namespaces/game/pods/processor-1");
+        assert_eq!(
+            body["metadata"]["labels"]["cube-harvest.io/unit-type"],
+            "miner"
+        );
+        assert_eq!(
+            body["metadata"]["annotations"][TARGET_ANNOTATION],
+            "10.0.0.2"
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! Rebindable keys of the game actions, set in the `keymap` of `settings.json`
+
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Game actions that can be bound to another key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Previous node or unit
+    NavLeft,
+    /// Next node or unit
+    NavRight,
+    /// Enter a node, confirm a unit or a list entry
+    Select,
+    /// Leave the current view or cancel
+    Back,
+    CreateUnit,
+    SelectMiner,
+    SelectProcessor,
+    Delete,
+}
+
+impl Action {
+    const ALL: [Self; 8] = [
+        Self::NavLeft,
+        Self::NavRight,
+        Self::Select,
+        Self::Back,
+        Self::CreateUnit,
+        Self::SelectMiner,
+        Self::SelectProcessor,
+        Self::Delete,
+    ];
+
+    fn default_key(self) -> KeyCode {
+        match self {
+            Self::NavLeft => KeyCode::Left,
+            Self::NavRight => KeyCode::Right,
+            Self::Select => KeyCode::Enter,
+            Self::Back => KeyCode::Escape,
+            Self::CreateUnit => KeyCode::C,
+            Self::SelectMiner => KeyCode::M,
+            Self::SelectProcessor => KeyCode::P,
+            Self::Delete => KeyCode::D,
+        }
+    }
+}
+
+/// Keys the game uses outside of the [`Action`]s, which can't be bound
+const FIXED_KEYS: [KeyCode; 33] = [
+    KeyCode::A,
+    KeyCode::H,
+    KeyCode::K,
+    KeyCode::L,
+    KeyCode::N,
+    KeyCode::O,
+    KeyCode::S,
+    KeyCode::U,
+    KeyCode::V,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::Slash,
+    KeyCode::Tab,
+    KeyCode::GraveAccent,
+    KeyCode::Space,
+    KeyCode::Up,
+    KeyCode::Down,
+    KeyCode::Backspace,
+    KeyCode::F3,
+    KeyCode::LeftShift,
+    KeyCode::RightShift,
+    KeyCode::LeftControl,
+    KeyCode::RightControl,
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+/// Names of the keys a binding may use, as in macroquad's `KeyCode`
+const KEY_NAMES: [(&str, KeyCode); 85] = [
+    ("Space", KeyCode::Space),
+    ("Apostrophe", KeyCode::Apostrophe),
+    ("Comma", KeyCode::Comma),
+    ("Minus", KeyCode::Minus),
+    ("Period", KeyCode::Period),
+    ("Slash", KeyCode::Slash),
+    ("Key0", KeyCode::Key0),
+    ("Key1", KeyCode::Key1),
+    ("Key2", KeyCode::Key2),
+    ("Key3", KeyCode::Key3),
+    ("Key4", KeyCode::Key4),
+    ("Key5", KeyCode::Key5),
+    ("Key6", KeyCode::Key6),
+    ("Key7", KeyCode::Key7),
+    ("Key8", KeyCode::Key8),
+    ("Key9", KeyCode::Key9),
+    ("Semicolon", KeyCode::Semicolon),
+    ("Equal", KeyCode::Equal),
+    ("A", KeyCode::A),
+    ("B", KeyCode::B),
+    ("C", KeyCode::C),
+    ("D", KeyCode::D),
+    ("E", KeyCode::E),
+    ("F", KeyCode::F),
+    ("G", KeyCode::G),
+    ("H", KeyCode::H),
+    ("I", KeyCode::I),
+    ("J", KeyCode::J),
+    ("K", KeyCode::K),
+    ("L", KeyCode::L),
+    ("M", KeyCode::M),
+    ("N", KeyCode::N),
+    ("O", KeyCode::O),
+    ("P", KeyCode::P),
+    ("Q", KeyCode::Q),
+    ("R", KeyCode::R),
+    ("S", KeyCode::S),
+    ("T", KeyCode::T),
+    ("U", KeyCode::U),
+    ("V", KeyCode::V),
+    ("W", KeyCode::W),
+    ("X", KeyCode::X),
+    ("Y", KeyCode::Y),
+    ("Z", KeyCode::Z),
+    ("LeftBracket", KeyCode::LeftBracket),
+    ("Backslash", KeyCode::Backslash),
+    ("RightBracket", KeyCode::RightBracket),
+    ("GraveAccent", KeyCode::GraveAccent),
+    ("Escape", KeyCode::Escape),
+    ("Enter", KeyCode::Enter),
+    ("Tab", KeyCode::Tab),
+    ("Backspace", KeyCode::Backspace),
+    ("Insert", KeyCode::Insert),
+    ("Delete", KeyCode::Delete),
+    ("Right", KeyCode::Right),
+    ("Left", KeyCode::Left),
+    ("Down", KeyCode::Down),
+    ("Up", KeyCode::Up),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("F1", KeyCode::F1),
+    ("F2", KeyCode::F2),
+    ("F3", KeyCode::F3),
+    ("F4", KeyCode::F4),
+    ("F5", KeyCode::F5),
+    ("F6", KeyCode::F6),
+    ("F7", KeyCode::F7),
+    ("F8", KeyCode::F8),
+    ("F9", KeyCode::F9),
+    ("F10", KeyCode::F10),
+    ("F11", KeyCode::F11),
+    ("F12", KeyCode::F12),
+    ("Kp0", KeyCode::Kp0),
+    ("Kp1", KeyCode::Kp1),
+    ("Kp2", KeyCode::Kp2),
+    ("Kp3", KeyCode::Kp3),
+    ("Kp4", KeyCode::Kp4),
+    ("Kp5", KeyCode::Kp5),
+    ("Kp6", KeyCode::Kp6),
+    ("Kp7", KeyCode::Kp7),
+    ("Kp8", KeyCode::Kp8),
+    ("Kp9", KeyCode::Kp9),
+    ("KpEnter", KeyCode::KpEnter),
+];
+
+fn parse_key(name: &str) -> Option<KeyCode> {
+    KEY_NAMES
+        .iter()
+        .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+        .map(|(_, key)| *key)
+}
+
+/// Name of `key` in the key hints, from [`KEY_NAMES`] with the arrows and
+/// escape shortened
+fn key_name(key: KeyCode) -> &'static str {
+    match key {
+        KeyCode::Left => "<-",
+        KeyCode::Right => "->",
+        KeyCode::Escape => "Esc",
+        _ => KEY_NAMES
+            .iter()
+            .find(|(_, named)| *named == key)
+            .map_or("?", |(name, _)| name),
+    }
+}
+
+/// Key of every [`Action`], the defaults overridden by the player's bindings
+#[derive(Debug, Clone)]
+pub struct KeyMap {
+    keys: HashMap<Action, KeyCode>,
+    /// Seconds a key is held before it repeats, and between repeats
+    repeat_delay: f64,
+    repeat_interval: f64,
+    /// `get_time` of the next repeat of the held keys
+    next_repeat: HashMap<KeyCode, f64>,
+}
+
+impl KeyMap {
+    /// Unknown key names and keys already in use are reported and keep the
+    /// default key
+    pub fn new(
+        bindings: &HashMap<Action, String>,
+        repeat_delay: f64,
+        repeat_interval: f64,
+    ) -> Self {
+        let mut keys = Action::ALL
+            .into_iter()
+            .map(|action| {
+                let key = match bindings.get(&action) {
+                    Some(name) => parse_key(name).unwrap_or_else(|| {
+                        println!("ignore unknown key {name:?} bound to {action:?}");
+                        action.default_key()
+                    }),
+                    None => action.default_key(),
+                };
+                (action, key)
+            })
+            .collect::<HashMap<_, _>>();
+        // the defaults don't clash, so this ends once every clashing binding
+        // is back to its default
+        while let Some((action, used_by)) = Action::ALL.into_iter().find_map(|action| {
+            let key = keys[&action];
+            if key == action.default_key() {
+                return None;
+            }
+            if FIXED_KEYS.contains(&key) {
+                return Some((action, "the game".to_string()));
+            }
+            Action::ALL
+                .into_iter()
+                .find(|other| *other != action && keys[other] == key)
+                .map(|other| (action, format!("{other:?}")))
+        }) {
+            println!(
+                "ignore key {} bound to {action:?}, it's already used by {used_by}",
+                key_name(keys[&action])
+            );
+            keys.insert(action, action.default_key());
+        }
+        Self {
+            keys,
+            repeat_delay,
+            repeat_interval,
+            next_repeat: HashMap::new(),
+        }
+    }
+
+    pub fn key(&self, action: Action) -> KeyCode {
+        self.keys[&action]
+    }
+
+    /// Name of the key bound to `action`, as shown in the key hints
+    pub fn key_name(&self, action: Action) -> &'static str {
+        key_name(self.key(action))
+    }
+
+    pub fn is_action_pressed(&self, action: Action) -> bool {
+        is_key_pressed(self.key(action))
+    }
+
+    /// See [`KeyMap::is_key_repeated`]
+    pub fn is_action_repeated(&mut self, action: Action) -> bool {
+        self.is_key_repeated(self.key(action))
+    }
+
+    /// Pressed this frame, or held past the repeat delay and due for another
+    /// repeat. Call it once a frame per key, it keeps track of the repeats.
+    pub fn is_key_repeated(&mut self, key: KeyCode) -> bool {
+        let now = get_time();
+        if is_key_pressed(key) {
+            self.next_repeat.insert(key, now + self.repeat_delay);
+            return true;
+        }
+        if !is_key_down(key) {
+            self.next_repeat.remove(&key);
+            return false;
+        }
+        match self.next_repeat.get_mut(&key) {
+            Some(next) if now >= *next => {
+                // at most one repeat a frame, dropping the ones a slow frame
+                // missed
+                *next = (*next + self.repeat_interval).max(now);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(bindings: &[(Action, &str)]) -> KeyMap {
+        let bindings = bindings
+            .iter()
+            .map(|(action, name)| (*action, name.to_string()))
+            .collect();
+        KeyMap::new(&bindings, 0.4, 0.08)
+    }
+
+    #[test]
+    fn clashing_bindings_keep_their_default() {
+        let keymap = keymap(&[(Action::Delete, "L"), (Action::CreateUnit, "P")]);
+        assert_eq!(keymap.key(Action::Delete), KeyCode::D);
+        assert_eq!(keymap.key(Action::CreateUnit), KeyCode::C);
+        assert_eq!(keymap.key(Action::SelectProcessor), KeyCode::P);
+    }
+
+    #[test]
+    fn keys_can_be_swapped() {
+        let keymap = keymap(&[(Action::NavLeft, "Right"), (Action::NavRight, "Left")]);
+        assert_eq!(keymap.key(Action::NavLeft), KeyCode::Right);
+        assert_eq!(keymap.key(Action::NavRight), KeyCode::Left);
+    }
+}
@@ -1,701 +1,400 @@
-use askama::Template;
+mod economy;
+mod game;
+mod k8s;
+mod keymap;
+mod render;
+mod settings;
+mod simulate;
+mod sound;
+mod state;
+
+use crate::economy::GameConfig;
+use crate::game::open_game_window;
+use crate::k8s::{
+    Backoff, ClusterBackend, CubeHarvestError, ErrorCategory, GameMessage, GameResources,
+    KubeBackend, PendingCreations, SESSION_ID, WatchCache, apply_pod_quota, connect, get_unit_name,
+    watch_nodes, watch_pods,
+};
+use crate::settings::Settings;
+use crate::simulate::SimulatedCluster;
+use crate::state::KubeContexts;
+use clap::Parser;
 use core::panic;
-use k8s_openapi::api::core::v1::Node;
-use k8s_openapi::api::core::v1::Pod;
-use kube::api::DeleteParams;
-use kube::api::PostParams;
-use kube::{Api, Client, Config, api::ListParams};
-use macroquad::experimental::collections::storage;
-use macroquad::prelude::coroutines::start_coroutine;
-use macroquad::prelude::coroutines::wait_seconds;
+use futures::StreamExt;
+use kube::Client;
+use kube::runtime::watcher;
 use macroquad::prelude::*;
-use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::time::Duration;
 use tokio::sync::mpsc;
-use tokio::sync::mpsc::Receiver;
-use tokio::sync::mpsc::Sender;
-use tokio::task::JoinHandle;
-
-const MOVEMENT_SPEED: f32 = 200.;
-
-enum GameStage {
-    MainMenu,
-    Playing,
-    Paused,
-    GameOver,
-}
-
-struct Shape {
-    size: f32,
-    speed: f32,
-    x: f32,
-    y: f32,
-    collided: bool,
-}
-
-#[derive(Template, Debug)]
-#[template(path = "astro-unit.json", escape = "none")]
-struct AstroUnitTemplate {
-    name: String,
-    target_ip: String,
-    unit_type: String,
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Namespace to play in, defaults to the kubeconfig's current namespace
+    #[arg(short, long)]
+    namespace: Option<String>,
+    /// Play on an in-memory cluster instead of the one in the kubeconfig
+    #[arg(long)]
+    simulate: bool,
+    /// Player name put on created units, only these units are shown until
+    /// toggled with [O]
+    #[arg(long)]
+    owner: Option<String>,
 }
 
-struct GameResources {
-    pods: Vec<Pod>,
-    nodes: Vec<Node>,
-}
-
-impl GameResources {
-    // TODO: error handling
-    pub async fn new(client: &Client) -> Self {
-        let list_params = ListParams::default();
-        let pods = Api::default_namespaced(client.clone())
-            .list(&list_params)
-            .await
-            .expect("failed to get pods");
-        let nodes = Api::all(client.clone())
-            .list(&list_params)
-            .await
-            .expect("failed to get nodes");
-
-        Self {
-            pods: pods.items,
-            nodes: nodes.items,
+/// Connect and list the cluster for the game to start on, waiting out
+/// apiserver hiccups
+async fn first_snapshot(
+    namespace: Option<String>,
+    owner: Option<&str>,
+    processor_capacity: usize,
+) -> (Client, String, GameResources) {
+    let mut backoff = Backoff::new();
+    let client = loop {
+        match connect(None).await {
+            Ok(client) => break client,
+            Err(err) => {
+                println!("failed to connect to the cluster, retrying: {err}");
+                tokio::time::sleep(backoff.next()).await;
+            }
         }
+    };
+    // the client's default namespace falls back to `default` without a
+    // current namespace in the kubeconfig
+    let namespace = namespace.unwrap_or_else(|| client.default_namespace().to_string());
+    println!("namespace: {namespace}");
+    let game_resources = loop {
+        match GameResources::new(&client, &namespace, owner, processor_capacity).await {
+            Ok(game_resources) => break game_resources,
+            Err(err) => {
+                println!("failed to get game resources, retrying: {err}");
+                tokio::time::sleep(backoff.next()).await;
+            }
+        }
+    };
+    if !game_resources.node_view {
+        println!(
+            "no permission to list nodes, node view disabled: grouping pods by their node name only"
+        );
     }
+    (client, namespace, game_resources)
 }
 
-#[derive(Debug, Clone)]
-enum NavigationMode {
-    Cluster,
-    Node,
-    Create,
-}
-
-#[derive(Debug, Clone)]
-enum CreateTarget {
-    Miner,
-    Processor,
-}
-
-#[derive(Debug, Clone)]
-struct GameState {
-    selected_node_index: usize,
-    navigation_mode: NavigationMode,
-    create_target: Option<CreateTarget>,
-    create_text_buf: String,
-    credits: usize,
-    miner_price: usize,
-    processor_price: usize,
+/// Tell the player why an action didn't go through, e.g. the apiserver's
+/// message when it rejected a unit. Fails once the game window is gone.
+async fn report_action_failure(
+    tx: &mpsc::Sender<GameMessage>,
+    err: CubeHarvestError,
+) -> Result<(), CubeHarvestError> {
+    println!("{err}");
+    // nobody left to tell
+    if matches!(err, CubeHarvestError::Channel) {
+        return Err(err);
+    }
+    tx.send(GameMessage::ActionFailed(err.to_string())).await?;
+    Ok(())
 }
 
-impl Shape {
-    fn collides_with(&self, other: &Self) -> bool {
-        self.rect().overlaps(&other.rect())
+/// Apply a game action, telling the player when it didn't go through. Fails
+/// once the game window is gone, the loop should stop then.
+async fn apply_action(
+    backend: &mut impl ClusterBackend,
+    msg: GameMessage,
+    tx: &mpsc::Sender<GameMessage>,
+) -> Result<(), CubeHarvestError> {
+    match backend.apply(msg, tx).await {
+        Ok(()) => Ok(()),
+        Err(err) => report_action_failure(tx, err).await,
     }
+}
 
-    fn rect(&self) -> Rect {
-        Rect {
-            x: self.x - self.size / 2.,
-            y: self.y - self.size / 2.,
-            w: self.size,
-            h: self.size,
+/// Stand-in for the reconciliation loop with `--simulate`, sending a snapshot
+/// of the simulated cluster after every action
+async fn simulate(
+    mut cluster: SimulatedCluster,
+    tx: mpsc::Sender<GameMessage>,
+    mut k_rx: mpsc::Receiver<GameMessage>,
+    mut shutdown_rx: watch::Receiver<bool>,
+) {
+    println!("simulating a cluster, nothing is sent to kubernetes");
+    loop {
+        if tx
+            .send(GameMessage::UpdateResources(cluster.snapshot()))
+            .await
+            .is_err()
+        {
+            break;
+        }
+        let msg = tokio::select! {
+            _ = shutdown_rx.changed() => break,
+            msg = k_rx.recv() => msg,
+        };
+        match msg {
+            // nothing to poll while the game sleeps
+            Some(GameMessage::Sleep | GameMessage::Wake) => {}
+            Some(msg) => {
+                if apply_action(&mut cluster, msg, &tx).await.is_err() {
+                    break;
+                }
+            }
+            None => break,
         }
     }
 }
 
 #[tokio::main]
 async fn main() {
-    // setup kube client
-    let config = Config::infer().await.expect("failed to load kubeconfig");
-    let client = Client::try_from(config).expect("failed to create kube client");
-    let game_resources = GameResources::new(&client).await;
+    let args = Args::parse();
+    println!("session id: {}", *SESSION_ID);
+
+    let mut settings = Settings::load();
+    settings.owner = args.owner.clone();
+    let game_config = GameConfig::load();
+
+    let creation_timeout = settings.creation_timeout_seconds.map(Duration::from_secs);
+    let pod_quota = settings.max_units.filter(|_| settings.enforce_quota);
     let (tx, rx) = mpsc::channel(0x20);
-    tx.send(GameMessage::UpdateResources(game_resources))
-        .await
-        .expect("failed to send game msg");
     let (k_tx, mut k_rx) = mpsc::channel(0x20);
-
-    // TODO: handle exiting game
-    let reconciliation_loop = tokio::spawn(async move {
-        loop {
-            let game_resources = GameResources::new(&client).await;
-            tx.send(GameMessage::UpdateResources(game_resources))
+    let (shutdown_tx, mut shutdown_rx) = watch::channel(false);
+    let processor_capacity = game_config.processor_capacity;
+    let update_interval = Duration::from_millis(game_config.update_interval_millis);
+
+    let contexts = if args.simulate {
+        KubeContexts::default()
+    } else {
+        KubeContexts::load()
+    };
+    let reconciliation_loop = if args.simulate {
+        tokio::spawn(simulate(
+            SimulatedCluster::new(game_config.clone(), args.owner.clone()),
+            tx,
+            k_rx,
+            shutdown_rx,
+        ))
+    } else {
+        tokio::spawn(async move {
+            // the game window shows a loading screen until the first snapshot
+            let (client, namespace, game_resources) = tokio::select! {
+                _ = shutdown_rx.changed() => return,
+                first = first_snapshot(args.namespace.clone(), args.owner.as_deref(), processor_capacity) => first,
+            };
+            // the game window closed while connecting
+            if tx
+                .send(GameMessage::UpdateResources(game_resources))
                 .await
-                .expect("failed to send game msg");
-            match k_rx.try_recv() {
-                Ok(msg) => match msg {
-                    GameMessage::CreatePod(pod) => {
-                        let api = Api::default_namespaced(client.clone());
-                        api.create(&PostParams::default(), &pod)
-                            .await
-                            .expect("failed to create pod");
-                    }
-                    GameMessage::DeletePod(name) => {
-                        let api = Api::<Pod>::default_namespaced(client.clone());
-                        api.delete(&name, &DeleteParams::default())
-                            .await
-                            .expect("failed to delete pod");
-                    }
-                    GameMessage::UpdateResources(_) => unreachable!(),
-                },
-                Err(err) => {
-                    if !matches!(err, mpsc::error::TryRecvError::Empty) {
-                        panic!("{err}");
-                    }
-                }
-            }
-            tokio::time::sleep(Duration::from_secs(1)).await;
-        }
-    });
-
-    // Because macroquad need to be executed on one thread, we open it
-    // from tokio main function
-    // ref: https://github.com/not-fl3/macroquad/issues/182#issuecomment-1001571263
-    let game_window_handle = open_game_window(rx, k_tx);
-
-    game_window_handle.await.unwrap();
-    reconciliation_loop.await.unwrap();
-}
-
-enum GameMessage {
-    UpdateResources(GameResources),
-    CreatePod(Pod),
-    /// Delete pod by name
-    DeletePod(String),
-}
-
-fn open_game_window(rx: Receiver<GameMessage>, k_tx: Sender<GameMessage>) -> JoinHandle<()> {
-    tokio::task::spawn_blocking(|| {
-        macroquad::Window::from_config(
-            Conf {
-                sample_count: 4,
-                window_title: "CubeHarvest: Cluster Frontier".to_string(),
-                high_dpi: true,
-                ..Default::default()
-            },
-            draw(rx, k_tx),
-        );
-    })
-}
-
-async fn draw(mut rx: Receiver<GameMessage>, k_tx: Sender<GameMessage>) {
-    rand::srand(miniquad::date::now() as u64);
-    set_pc_assets_folder("assets");
-
-    storage::store(GameState {
-        selected_node_index: 0,
-        navigation_mode: NavigationMode::Cluster,
-        create_target: None,
-        create_text_buf: "".to_string(),
-        credits: 0,
-        miner_price: 0,
-        processor_price: 0,
-    });
-
-    let mut game_stage = GameStage::MainMenu;
-    // call after loading all textures
-    build_textures_atlas();
-
-    // game loop
-    loop {
-        clear_background(BLACK);
-
-        // consume messages
-        loop {
-            match rx.try_recv() {
-                Ok(msg) => match msg {
-                    GameMessage::UpdateResources(game_resources) => storage::store(game_resources),
-                    GameMessage::DeletePod(_) | GameMessage::CreatePod(_) => unreachable!(),
-                },
-                Err(err) => {
-                    if matches!(err, mpsc::error::TryRecvError::Empty) {
-                        break;
-                    }
-                    panic!("{err}");
-                }
+                .is_err()
+            {
+                return;
             }
-        }
-
-        {
-            let mut game_state = storage::get::<GameState>().clone();
-            game_state.miner_price = storage::get::<GameResources>()
-                .pods
-                .iter()
-                .filter(|p| matches!(get_unit_type(p).as_deref(), Some("miner")))
-                .count();
-            game_state.processor_price = storage::get::<GameResources>()
-                .pods
-                .iter()
-                .filter(|p| matches!(get_unit_type(p).as_deref(), Some("processor")))
-                .count();
-            storage::store(game_state);
-        }
-
-        match game_stage {
-            GameStage::MainMenu => {
-                // update
-                if is_key_pressed(KeyCode::Escape) {
-                    std::process::exit(0);
-                }
-
-                if is_key_pressed(KeyCode::Space) {
-                    game_stage = GameStage::Playing;
-                    start_update_credits();
-                    start_spawn_monkeys(k_tx.clone());
+            if let Some(max_units) = pod_quota {
+                match apply_pod_quota(&client, &namespace, max_units).await {
+                    Ok(()) => println!("limited {namespace} to {max_units} pods"),
+                    Err(err) => println!("failed to create the pod quota: {err}"),
                 }
-
-                // draw
-                let text = "Press space";
-                let text_dimestions = measure_text(text, None, 50, 1.);
-                draw_text(
-                    text,
-                    screen_width() / 2. - text_dimestions.width / 2.,
-                    screen_height() / 2.,
-                    50.,
-                    WHITE,
-                );
             }
-            GameStage::Playing => {
-                // update
-                let delta_time = get_frame_time();
-                let mut game_state = storage::get_mut::<GameState>().clone();
-                let nodes_len = {
-                    let game_resources = storage::get::<GameResources>();
-                    game_resources.nodes.len()
-                };
-
-                match game_state.navigation_mode {
-                    NavigationMode::Cluster => {
-                        if is_key_pressed(KeyCode::Right) {
-                            game_state.selected_node_index =
-                                game_state.selected_node_index.saturating_add(1);
-                        }
-                        if is_key_pressed(KeyCode::Left) {
-                            game_state.selected_node_index =
-                                game_state.selected_node_index.saturating_sub(1);
-                        }
-                        if is_key_pressed(KeyCode::Enter) {
-                            game_state.navigation_mode = NavigationMode::Node;
-                        }
-                        if is_key_pressed(KeyCode::C) {
-                            game_state.navigation_mode = NavigationMode::Create;
-                            game_state.create_text_buf.clear();
-                            game_state.create_target = None;
-                        }
-                    }
-                    NavigationMode::Node => {
-                        if is_key_pressed(KeyCode::Escape) {
-                            game_state.navigation_mode = NavigationMode::Cluster;
+            let mut backend = KubeBackend {
+                client,
+                namespace,
+                pending_creations: PendingCreations::new(creation_timeout),
+            };
+            // `None` follows the inferred config until a context is picked
+            let mut context: Option<String> = None;
+            // `None` follows the units of every owner
+            let mut owner = args.owner.clone();
+            let mut connected = true;
+            // rebuild the client when the cluster is gone, in case the kubeconfig
+            // changed in the meantime
+            let mut backoff = Backoff::new();
+            let mut reconnect_at = None;
+            let mut pods = WatchCache::default();
+            let mut pod_events = watch_pods(&backend.client, &backend.namespace, owner.as_deref());
+            // `None` once listing nodes turned out to be forbidden
+            let mut nodes = Some(WatchCache::default());
+            let mut node_events = Some(watch_nodes(&backend.client));
+            // changes are batched and sent at most this often
+            let mut update_interval = tokio::time::interval(update_interval);
+            let mut changed = false;
+            // messages received while sleeping that are handled once awake
+            let mut deferred = VecDeque::new();
+            // sends only fail once the game window is gone, that ends the loop
+            'reconcile: loop {
+                tokio::select! {
+                    _ = shutdown_rx.changed() => break,
+                    Some(event) = pod_events.next() => match event {
+                        Ok(event) => {
+                            if let watcher::Event::Apply(pod) | watcher::Event::InitApply(pod) = &event
+                                && let Some(name) = get_unit_name(pod)
+                            {
+                                backend.pending_creations.seen(name);
+                            }
+                            changed |= pods.apply(event);
+                            if !connected {
+                                connected = true;
+                                backoff.reset();
+                                reconnect_at = None;
+                                if tx.send(GameMessage::ConnectionRestored).await.is_err() {
+                                    break;
+                                }
+                            }
                         }
-
-                        if is_key_pressed(KeyCode::D) {
-                            // TODO: delete selected unit
+                        Err(err) => {
+                            let err = CubeHarvestError::from(err);
+                            println!("failed to watch pods: {err}");
+                            connected = false;
+                            if reconnect_at.is_none() && err.category() == ErrorCategory::Connectivity {
+                                reconnect_at = Some(Instant::now() + backoff.next());
+                            }
+                            if tx.send(GameMessage::ConnectionLost(err)).await.is_err() {
+                                break;
+                            }
                         }
-                        if is_key_pressed(KeyCode::Right) {
-                            // TODO: update unit selection
+                    },
+                    Some(event) = async {
+                        match node_events.as_mut() {
+                            Some(node_events) => node_events.next().await,
+                            None => std::future::pending().await,
                         }
-                        if is_key_pressed(KeyCode::Left) {
-                            // TODO: update unit selection
+                    } => match event {
+                        Ok(event) => {
+                            if let Some(nodes) = nodes.as_mut() {
+                                changed |= nodes.apply(event);
+                            }
                         }
-                    }
-                    NavigationMode::Create => match &game_state.create_target {
-                        None => {
-                            if is_key_pressed(KeyCode::Escape) {
-                                game_state.navigation_mode = NavigationMode::Cluster;
+                        Err(err) => {
+                            let err = CubeHarvestError::from(err);
+                            if err.category() == ErrorCategory::Permission {
+                                println!("no permission to watch nodes, node view disabled: grouping pods by their node name only");
+                                nodes = None;
+                                node_events = None;
+                                changed = true;
+                            } else {
+                                println!("failed to watch nodes: {err}");
                             }
-
-                            if is_key_pressed(KeyCode::M) {
-                                game_state.create_target = Some(CreateTarget::Miner);
+                        }
+                    },
+                    _ = tokio::time::sleep_until(reconnect_at.unwrap_or_else(Instant::now)),
+                        if reconnect_at.is_some() =>
+                    {
+                        match connect(context.as_deref()).await {
+                            Ok(new_client) => {
+                                println!("reconnected to the cluster");
+                                backend.client = new_client;
+                                pod_events = watch_pods(&backend.client, &backend.namespace, owner.as_deref());
+                                if node_events.is_some() {
+                                    node_events = Some(watch_nodes(&backend.client));
+                                }
+                                reconnect_at = None;
                             }
-                            if is_key_pressed(KeyCode::P) {
-                                game_state.create_target = Some(CreateTarget::Processor);
+                            Err(err) => {
+                                println!("failed to reconnect to the cluster: {err}");
+                                reconnect_at = Some(Instant::now() + backoff.next());
                             }
                         }
-                        Some(target) => {
-                            if is_key_pressed(KeyCode::Enter)
-                                || matches!(target, CreateTarget::Processor)
-                            {
-                                let has_enough_credit = match target {
-                                    CreateTarget::Miner => {
-                                        game_state.credits >= game_state.miner_price
-                                    }
-                                    CreateTarget::Processor => {
-                                        game_state.credits >= game_state.processor_price
-                                    }
+                    }
+                    msg = async {
+                        match deferred.pop_front() {
+                            Some(msg) => Some(msg),
+                            None => k_rx.recv().await,
+                        }
+                    } => match msg {
+                        Some(GameMessage::Sleep) => {
+                            // stop following the cluster until the game wakes up,
+                            // the watchers catch up on what they missed afterwards
+                            loop {
+                                let msg = tokio::select! {
+                                    _ = shutdown_rx.changed() => return,
+                                    msg = k_rx.recv() => msg,
                                 };
-
-                                if has_enough_credit {
-                                    let astro_unit = create_unit(&game_state, target);
-                                    println!("Create {target:?} -> {}", game_state.create_text_buf);
-                                    k_tx.blocking_send(GameMessage::CreatePod(astro_unit))
-                                        .expect("failed to request creating pod");
-                                    match target {
-                                        CreateTarget::Miner => {
-                                            game_state.credits -= game_state.miner_price;
-                                        }
-                                        CreateTarget::Processor => {
-                                            game_state.credits -= game_state.processor_price;
+                                match msg {
+                                    None => return,
+                                    Some(GameMessage::Wake) => break,
+                                    // these restart the watchers, once awake
+                                    Some(
+                                        msg @ (GameMessage::SwitchContext(_)
+                                        | GameMessage::FilterOwner(_)),
+                                    ) => deferred.push_back(msg),
+                                    Some(msg) => {
+                                        if apply_action(&mut backend, msg, &tx).await.is_err() {
+                                            return;
                                         }
                                     }
-                                } else {
-                                    // TODO: alert
                                 }
-
-                                game_state.navigation_mode = NavigationMode::Cluster;
-                            } else if is_key_pressed(KeyCode::Escape) {
-                                game_state.navigation_mode = NavigationMode::Cluster;
-                            } else if is_key_pressed(KeyCode::Backspace) {
-                                game_state.create_text_buf.pop();
-                            } else if let Some(c) = get_char_pressed() {
-                                if c.is_digit(10) || c == '.' {
-                                    game_state.create_text_buf.push(c);
+                            }
+                        }
+                        Some(GameMessage::SwitchContext(name)) => match connect(Some(&name)).await {
+                            Ok(new_client) => {
+                                println!("switched to context {name}");
+                                backend.client = new_client;
+                                context = Some(name.clone());
+                                if args.namespace.is_none() {
+                                    backend.namespace = backend.client.default_namespace().to_string();
+                                }
+                                // the caches are replaced once the new watchers listed
+                                // everything, keeping the old cluster on screen until then
+                                pod_events = watch_pods(&backend.client, &backend.namespace, owner.as_deref());
+                                nodes = Some(nodes.unwrap_or_default());
+                                node_events = Some(watch_nodes(&backend.client));
+                                backoff.reset();
+                                reconnect_at = None;
+                                if tx.send(GameMessage::ContextSwitched(name)).await.is_err() {
+                                    break;
+                                }
+                            }
+                            Err(err) => {
+                                println!("failed to switch to context {name}: {err}");
+                                if tx.send(GameMessage::ContextSwitchFailed(name, err)).await.is_err() {
+                                    break;
                                 }
                             }
+                        },
+                        Some(GameMessage::FilterOwner(filter)) => {
+                            owner = filter;
+                            // like switching contexts, the pods on screen are
+                            // replaced once the new watcher listed everything
+                            pod_events = watch_pods(&backend.client, &backend.namespace, owner.as_deref());
                         }
-                    },
-                }
-
-                game_state.selected_node_index =
-                    clamp(game_state.selected_node_index, 0, nodes_len - 1);
-                // post update
-                storage::store(game_state);
-
-                draw_top_panel();
-                draw_node();
-                draw_navbar();
-            }
-            GameStage::Paused => {
-                if is_key_pressed(KeyCode::Space) {
-                    game_stage = GameStage::Playing;
-                }
-
-                let text = "Paused";
-                let text_dimensions = measure_text(text, None, 50, 1.);
-                draw_text(
-                    text,
-                    screen_width() / 2. - text_dimensions.width / 2.,
-                    screen_height() / 2.,
-                    50.,
-                    WHITE,
-                );
-            }
-            GameStage::GameOver => {
-                if is_key_pressed(KeyCode::Space) {
-                    game_stage = GameStage::MainMenu;
-                }
-
-                let text = "GAME OVER!";
-                let text_dimensions = measure_text(text, None, 50, 1.);
-                draw_text(
-                    text,
-                    screen_width() / 2. - text_dimensions.width / 2.,
-                    screen_height() / 2.,
-                    50.,
-                    RED,
-                );
-            }
-        };
-
-        next_frame().await
-    }
-}
-
-fn start_spawn_monkeys(k_tx: Sender<GameMessage>) {
-    start_coroutine(async move {
-        loop {
-            if rand::gen_range(0, 100) > 95 {
-                println!("starting deleting pod");
-                let game_resources = storage::get::<GameResources>();
-                if !game_resources.pods.is_empty() {
-                    let i = rand::gen_range(0, game_resources.pods.len());
-                    match game_resources.pods[i].metadata.name.as_ref() {
-                        Some(pod_name) => {
-                            k_tx.blocking_send(GameMessage::DeletePod(pod_name.to_string()))
-                                .expect("failed to request deleting pod");
+                        Some(msg) => {
+                            if apply_action(&mut backend, msg, &tx).await.is_err() {
+                                break;
+                            }
                         }
-                        None => {
-                            println!("pod {i} does not have name");
+                        // the game window is gone
+                        None => break,
+                    },
+                    _ = update_interval.tick() => {
+                        for name in backend.pending_creations.vanished() {
+                            println!("Unit {name} was created but did not appear");
+                            if tx.send(GameMessage::CreateFailed(name)).await.is_err() {
+                                break 'reconcile;
+                            }
                         }
-                    };
-                }
-            }
-            wait_seconds(3.).await;
-        }
-    });
-}
-
-fn create_unit(game_state: &GameState, target: &CreateTarget) -> Pod {
-    let unit_id = rand::rand();
-    let unit_type = match target {
-        CreateTarget::Miner => "miner",
-        CreateTarget::Processor => "processor",
-    }
-    .to_string();
-    let astro_unit = AstroUnitTemplate {
-        name: format!("{unit_type}-{unit_id}"),
-        target_ip: game_state.create_text_buf.clone(),
-        unit_type,
-    }
-    .render()
-    .unwrap();
-    let astro_unit =
-        serde_json::from_str::<Pod>(&astro_unit).expect("failed to parse astro unit json");
-    astro_unit
-}
-
-fn start_update_credits() {
-    start_coroutine(earn_credits());
-    start_coroutine(consume_credits());
-}
-
-async fn earn_credits() {
-    loop {
-        {
-            let earned_credits = {
-                let mut m = HashMap::new();
-                let game_resources = storage::get::<GameResources>();
-                for p in &game_resources.pods {
-                    if matches!(get_unit_type(p).as_deref(), Some("processor")) {
-                        let Some(ip) = get_unit_ip(p).to_owned() else {
-                            continue;
-                        };
-                        m.insert(ip, 0);
-                    }
-                }
-
-                for p in &game_resources.pods {
-                    if matches!(get_unit_type(p).as_deref(), Some("miner")) {
-                        let Some(target_ip) = p
-                            .spec
-                            .as_ref()
-                            .and_then(|s| s.containers[0].env.as_ref())
-                            .and_then(|e| e.iter().find(|e| e.name == "TARGET"))
-                            .and_then(|e| e.value.clone())
-                        else {
-                            continue;
-                        };
-                        if let Some(c) = m.get_mut(target_ip.as_str()) {
-                            *c += 1;
+                        if changed {
+                            changed = false;
+                            let game_resources = GameResources::from_items(
+                                pods.items.values().cloned().collect(),
+                                nodes.as_ref().map(|n| n.items.values().cloned().collect()),
+                                processor_capacity,
+                            );
+                            if tx.send(GameMessage::UpdateResources(game_resources)).await.is_err() {
+                                break;
+                            }
                         }
                     }
                 }
-
-                m.into_values().map(|x| x.min(3)).sum::<usize>()
-            };
-            {
-                let mut game_state = storage::get_mut::<GameState>();
-                game_state.credits = game_state.credits.saturating_add(earned_credits);
             }
-        }
-        wait_seconds(1.).await;
-    }
-}
-
-async fn consume_credits() {
-    loop {
-        {
-            let consumed_credits = storage::get::<GameResources>().pods.len();
-            {
-                let mut game_state = storage::get_mut::<GameState>();
-                game_state.credits = game_state.credits.saturating_sub(consumed_credits);
-            }
-        }
-        wait_seconds(3.).await;
-    }
-}
-
-fn get_unit_ip(p: &Pod) -> Option<&str> {
-    p.status.as_ref().and_then(|s| s.pod_ip.as_deref())
-}
-
-fn draw_top_panel() {
-    let game_state = storage::get::<GameState>().clone();
-    let game_resources = storage::get::<GameResources>();
-
-    let label_size = 25;
-    let label_scale = 1.0;
-    let label_padding = 4.0;
-    let label_dimensions = measure_text("Placeholder", None, label_size, label_scale);
-    draw_text(
-        &format!("Astro Units: {}", game_resources.pods.len()),
-        10.0,
-        35.0,
-        label_size as f32,
-        WHITE,
-    );
-    draw_text(
-        &format!("Credits    : {}", game_state.credits),
-        10.0,
-        35.0 + (label_dimensions.height + label_padding) * 2.,
-        label_size as f32,
-        WHITE,
-    );
-    draw_text(
-        &format!("Astro Node : {}", game_state.selected_node_index),
-        10.0,
-        35.0 + label_dimensions.height + label_padding,
-        label_size as f32,
-        WHITE,
-    );
-}
-
-fn draw_node() {
-    let width = screen_width();
-    let height = screen_height();
-    let node_index = storage::get::<GameState>().selected_node_index;
-    let game_resources = storage::get::<GameResources>();
-    let node = &game_resources.nodes[node_index];
-    let node_name = node.metadata.name.as_ref().expect("nodes should have name");
-    let pods = game_resources
-        .pods
-        .iter()
-        .filter(|p| {
-            p.spec
-                .as_ref()
-                .and_then(|s| s.node_name.as_ref())
-                .map(|nn| nn == node_name)
-                .unwrap_or(false)
         })
-        .collect::<Vec<_>>();
-
-    // draw node plane
-    let node_width = width * 0.7;
-    let node_height = 100.;
-    draw_rectangle(
-        width / 2. - node_width / 2.,
-        height - node_height / 2.,
-        node_width,
-        node_height,
-        WHITE,
-    );
-
-    // draw pods info
-    let pod_size = 32.;
-    let gap = pod_size * 3.;
-    for (i, p) in pods.iter().enumerate() {
-        match get_unit_type(p).as_deref() {
-            Some("miner") => {
-                draw_miner(
-                    p,
-                    width / 2. - 200. + gap * i as f32,
-                    height - node_height / 2. + 15. - pod_size / 2.,
-                    pod_size,
-                    BLUE,
-                );
-            }
-            _ => {
-                draw_processor(
-                    p,
-                    width / 2. - 200. + gap * i as f32,
-                    height - node_height / 2. + 15. - pod_size / 2. - 48.,
-                    pod_size,
-                    PINK,
-                );
-            }
-        }
-    }
-    // draw_text(&format!("{}", pods.len()), 0., height - 10., 18., WHITE);
-}
-
-fn get_unit_type(p: &Pod) -> Option<String> {
-    p.metadata
-        .labels
-        .as_ref()
-        .and_then(|l| l.get("cube-harvest.io/unit-type").cloned())
-}
-
-fn draw_miner(pod: &Pod, x: f32, y: f32, size: f32, color: Color) {
-    // Main body (simple rectangle or custom polygon)
-    draw_rectangle(x - size / 2.0, y - size / 2.0, size, size, color);
+    };
 
-    // a small "engine" or "sensor" part
-    draw_triangle(
-        vec2(x - size / 4.0, y + size / 2.0),
-        vec2(x + size / 4.0, y + size / 2.0),
-        vec2(x, y + size / 2.0 + size / 4.0),
-        GRAY,
-    );
-
-    if let Some(ip) = get_unit_ip(pod) {
-        draw_text(&ip.to_string(), x - size / 2.0, y, 18., WHITE);
-    }
-}
-
-fn draw_processor(pod: &Pod, x: f32, y: f32, size: f32, color: Color) {
-    // Main body (simple rectangle or custom polygon)
-    draw_rectangle(x - size / 2.0, y - size / 2.0, size, size, color);
-
-    // a small "engine" or "sensor" part
-    draw_triangle(
-        vec2(x - size / 4.0, y - size / 2.0),
-        vec2(x + size / 4.0, y - size / 2.0),
-        vec2(x, y - size / 2.0 - size / 4.0),
-        GRAY,
-    );
-
-    if let Some(ip) = get_unit_ip(pod) {
-        draw_text(&ip.to_string(), x - size / 2.0, y, 18., WHITE);
-    }
-}
-
-fn draw_navbar() {
-    let width = screen_width();
-    let height = screen_height();
-    let navigation_mode = storage::get::<GameState>().navigation_mode.clone();
-
-    let label_font_size = 18;
-    let label_dim = measure_text("Cluster", None, label_font_size, 1.);
-    let padding = 4.;
-
-    // draw navbar background
-    draw_rectangle(
-        0.,
-        height - padding * 2. - label_dim.height - 5.,
-        width,
-        padding * 2. + label_dim.height + 10.,
-        GRAY,
-    );
+    // Because macroquad need to be executed on one thread, we open it
+    // from tokio main function
+    // ref: https://github.com/not-fl3/macroquad/issues/182#issuecomment-1001571263
+    let game_window_handle = open_game_window(rx, k_tx, settings, game_config, contexts);
 
-    // draw tooltip
-    let mut tooltip = String::with_capacity(0x50);
-    match navigation_mode {
-        NavigationMode::Cluster => {
-            tooltip.push_str("Cluster");
-            tooltip.push_str(" | [Enter] Select node");
-            tooltip.push_str(" | [<- ->] Switch node");
-            tooltip.push_str(" | [C]reate unit");
-        }
-        NavigationMode::Node => {
-            tooltip.push_str("Node   ");
-            tooltip.push_str(" | [Esc] Back");
-            tooltip.push_str(" | [<- ->] Switch unit");
-            tooltip.push_str(" | [D]elete unit");
-        }
-        NavigationMode::Create => {
-            tooltip.push_str("Create ");
-            let game_state = storage::get::<GameState>();
-            match game_state.create_target.as_ref() {
-                Some(target) => {
-                    tooltip.push_str(" | ");
-                    tooltip.push_str(&format!("{target:?}"));
-                    tooltip.push_str(" : ");
-                    tooltip.push_str(&game_state.create_text_buf);
-                }
-                None => {
-                    tooltip.push_str(" | [Esc] Back");
-                    tooltip.push_str(&format!(" | [M]iner (${})", game_state.miner_price));
-                    tooltip.push_str(&format!(" | [P]rocessor (${})", game_state.processor_price));
-                }
-            }
-        }
-    }
-    draw_text(
-        &tooltip,
-        0. + padding,
-        height - label_dim.height / 2. - padding,
-        18.,
-        WHITE,
-    );
+    game_window_handle.await.unwrap();
+    // the loop may be waiting on the cluster instead of on the game
+    shutdown_tx.send_replace(true);
+    reconciliation_loop.await.unwrap();
 }
@@ -0,0 +1,2175 @@
+//! Drawing the cluster, the node plane and the overlays
+
+use crate::economy::GameConfig;
+use crate::k8s::{
+    GameResources, SESSION_ID, get_session_id, get_unit_ip, get_unit_target, get_unit_type,
+    is_image_pull_failing, is_unschedulable, node_load, parse_quantity, pod_request,
+    processor_targets, restart_count, target_key, unit_age,
+};
+use crate::keymap::{Action, KeyMap};
+use crate::settings::{RenderStyle, Settings, UnitLabel};
+use crate::state::{
+    BlueprintAction, BuildQueue, CreateTarget, GameState, KubeContexts, ListColumn, NavigationMode,
+    UnitRow,
+};
+use k8s_openapi::api::core::v1::Node;
+use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::chrono::Utc;
+use macroquad::experimental::collections::storage;
+use macroquad::prelude::*;
+use macroquad_particles::{AtlasConfig, ColorCurve, EmitterConfig, EmittersCache};
+use std::collections::HashMap;
+use std::time::Duration;
+
+const NODE_PLANE_HEIGHT: f32 = 100.;
+const UNIT_SIZE: f32 = 32.;
+/// Size of a node in the cluster overview
+const NODE_CARD_SIZE: Vec2 = vec2(200., 140.);
+const NODE_CARD_GAP: f32 = 20.;
+/// Size of a unit drawn in miniature on a node card
+const MINI_UNIT_SIZE: f32 = 10.;
+/// Size of a node cell on the minimap
+const MINIMAP_CELL_SIZE: f32 = 12.;
+const MINIMAP_COLUMNS: usize = 10;
+/// Seconds an alert banner stays on screen
+pub const ALERT_SECONDS: f64 = 2.;
+/// Top of the strip of units waiting to be scheduled, below the top panel
+const STAGING_TOP: f32 = 130.;
+/// Seconds a unit takes to move from the staging area down to its node
+const LANDING_SECONDS: f64 = 0.6;
+/// Restarts a unit needs before it gets a restart badge
+const RESTART_BADGE_THRESHOLD: i32 = 3;
+
+/// Screen rectangles of what was drawn last frame, for picking with the mouse
+#[derive(Debug, Default)]
+pub struct HitRects {
+    /// Node planes by index into `GameResources::nodes`
+    pub nodes: Vec<(usize, Rect)>,
+    /// Units by index into the pods of the selected node
+    pub units: Vec<(usize, Rect)>,
+    /// Minimap cells by index into `GameResources::nodes`
+    pub minimap: Vec<(usize, Rect)>,
+}
+
+impl HitRects {
+    pub fn node_at(&self, point: Vec2) -> Option<usize> {
+        self.nodes
+            .iter()
+            .find(|(_, rect)| rect.contains(point))
+            .map(|(index, _)| *index)
+    }
+
+    pub fn unit_at(&self, point: Vec2) -> Option<usize> {
+        self.units
+            .iter()
+            .find(|(_, rect)| rect.contains(point))
+            .map(|(index, _)| *index)
+    }
+
+    pub fn minimap_at(&self, point: Vec2) -> Option<usize> {
+        self.minimap
+            .iter()
+            .find(|(_, rect)| rect.contains(point))
+            .map(|(index, _)| *index)
+    }
+}
+
+/// Units shown in the staging area last frame, and the ones on their way down
+/// to the node they were scheduled on
+#[derive(Default)]
+pub struct Landings {
+    /// Staging area positions by pod name
+    staged: HashMap<String, Vec2>,
+    flights: Vec<Landing>,
+}
+
+struct Landing {
+    is_miner: bool,
+    color: Color,
+    from: Vec2,
+    to: Vec2,
+    started: f64,
+}
+
+/// Particle bursts where units disappeared. Uses the frames of the optional
+/// `explosion.png` when it loaded, plain colored squares otherwise.
+pub struct Explosions {
+    pub emitters: EmittersCache,
+    /// Bursts where units first appeared
+    pub spawns: EmittersCache,
+}
+
+impl Explosions {
+    /// Frames of `explosion.png`, laid out in a single row
+    const ATLAS_FRAMES: u16 = 4;
+
+    pub async fn load() -> Self {
+        let texture = match load_texture("explosion.png").await {
+            Ok(texture) => Some(texture),
+            Err(err) => {
+                println!("texture explosion not loaded, using primitives: {err}");
+                None
+            }
+        };
+        Self {
+            emitters: EmittersCache::new(Self::config(texture)),
+            spawns: EmittersCache::new(Self::spawn_config()),
+        }
+    }
+
+    /// Slower, smaller burst than a deletion, fading from the unit colors
+    pub fn spawn_config() -> EmitterConfig {
+        EmitterConfig {
+            one_shot: true,
+            emitting: false,
+            lifetime: 0.5,
+            lifetime_randomness: 0.3,
+            explosiveness: 0.8,
+            amount: 16,
+            initial_direction_spread: 2. * std::f32::consts::PI,
+            initial_velocity: 60.,
+            initial_velocity_randomness: 0.5,
+            size: 4.,
+            size_randomness: 0.5,
+            colors_curve: ColorCurve {
+                start: WHITE,
+                mid: SKYBLUE,
+                end: Color::new(0.4, 0.8, 1., 0.),
+            },
+            ..Default::default()
+        }
+    }
+
+    pub fn config(texture: Option<Texture2D>) -> EmitterConfig {
+        let config = EmitterConfig {
+            one_shot: true,
+            emitting: false,
+            lifetime: 0.6,
+            lifetime_randomness: 0.3,
+            explosiveness: 0.9,
+            amount: 24,
+            initial_direction_spread: 2. * std::f32::consts::PI,
+            initial_velocity: 120.,
+            initial_velocity_randomness: 0.5,
+            size: 6.,
+            size_randomness: 0.5,
+            ..Default::default()
+        };
+        match texture {
+            Some(texture) => EmitterConfig {
+                size: 16.,
+                texture: Some(texture),
+                atlas: Some(AtlasConfig::new(Self::ATLAS_FRAMES, 1, ..)),
+                ..config
+            },
+            None => EmitterConfig {
+                colors_curve: ColorCurve {
+                    start: ORANGE,
+                    mid: RED,
+                    end: Color::new(0.3, 0.3, 0.3, 0.),
+                },
+                ..config
+            },
+        }
+    }
+}
+
+/// Optional sprites loaded from the assets folder. Draw functions fall back to
+/// primitive shapes for any texture that failed to load.
+#[derive(Default)]
+pub struct TextureRegistry {
+    pub textures: HashMap<&'static str, Texture2D>,
+}
+
+impl TextureRegistry {
+    const OPTIONAL_TEXTURES: [&'static str; 2] = ["miner", "processor"];
+
+    pub async fn load() -> Self {
+        let mut textures = HashMap::new();
+        for name in Self::OPTIONAL_TEXTURES {
+            match load_texture(&format!("{name}.png")).await {
+                Ok(texture) => {
+                    texture.set_filter(FilterMode::Nearest);
+                    textures.insert(name, texture);
+                }
+                Err(err) => println!("texture {name} not loaded, using primitives: {err}"),
+            }
+        }
+        Self { textures }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Texture2D> {
+        self.textures.get(name)
+    }
+}
+
+pub fn draw_top_panel() {
+    let game_state = storage::get::<GameState>().clone();
+    let game_resources = storage::get::<GameResources>();
+
+    let label_size = 25;
+    let label_scale = 1.0;
+    let label_padding = 4.0;
+    let label_dimensions = measure_text("Placeholder", None, label_size, label_scale);
+    let units_text = format!("Astro Units: {}", game_resources.pods.len());
+    draw_text(&units_text, 10.0, 35.0, label_size as f32, WHITE);
+    if game_state.cluster_error.is_some() {
+        let units_width = measure_text(&units_text, None, label_size, label_scale).width;
+        draw_text(
+            "disconnected - reconnecting...",
+            10.0 + units_width + 20.,
+            35.0,
+            label_size as f32,
+            RED,
+        );
+    }
+    // net income, to tell whether building more pays off
+    let net_rate = game_state.last_earn_rate - game_state.last_consume_rate;
+    let rate_text = format!(
+        "+{:.1}/s -{:.1}/s (net {net_rate:+.1})",
+        game_state.last_earn_rate, game_state.last_consume_rate
+    );
+    let rate_width = measure_text(&rate_text, None, 20, 1.).width;
+    let net_color = if net_rate > 0. {
+        GREEN
+    } else if net_rate < 0. {
+        RED
+    } else {
+        WHITE
+    };
+    draw_text(
+        &rate_text,
+        screen_width() - rate_width - 10.,
+        35.0,
+        20.,
+        net_color,
+    );
+    let credits_text = format!("Credits    : {}", game_state.credits);
+    let credits_y = 35.0 + (label_dimensions.height + label_padding) * 2.;
+    let settings = storage::get::<Settings>().clone();
+    // 0..1 oscillation for the threshold cues
+    let pulse = ((get_time() * 4.).sin() * 0.5 + 0.5) as f32;
+    let credits_warning = settings
+        .credits_low_threshold
+        .is_some_and(|low| game_state.credits < low);
+    let credits_color = if credits_warning {
+        Color::new(1., pulse * 0.3, pulse * 0.3, 1.)
+    } else if settings
+        .credits_high_threshold
+        .is_some_and(|high| game_state.credits >= high)
+    {
+        Color::new(1. - pulse, 1., 1. - pulse, 1.)
+    } else {
+        WHITE
+    };
+    draw_text(
+        &credits_text,
+        10.0,
+        credits_y,
+        label_size as f32,
+        credits_color,
+    );
+
+    // recent credit changes float up and fade out next to the credits
+    let delta_seconds = settings.credit_delta_seconds;
+    let credits_width = measure_text(&credits_text, None, label_size, label_scale).width;
+    if credits_warning {
+        draw_text(
+            "LOW",
+            10.0 + credits_width + 60.,
+            credits_y,
+            label_size as f32,
+            credits_color,
+        );
+    }
+    if settings.demo_speed() != 1. {
+        draw_text(
+            &format!("DEMO x{}", settings.demo_speed()),
+            10.0 + credits_width + 110.,
+            credits_y,
+            label_size as f32,
+            YELLOW,
+        );
+    }
+    let now = get_time();
+    for (delta, t) in &game_state.credit_deltas {
+        let progress = ((now - t) / delta_seconds).clamp(0., 1.) as f32;
+        let mut color = if *delta > 0 { GREEN } else { RED };
+        color.a = 1. - progress;
+        draw_text(
+            &format!("{delta:+}"),
+            10.0 + credits_width + 12.,
+            credits_y - progress * 20.,
+            20.,
+            color,
+        );
+    }
+    let node_text = format!(
+        "Astro Node : {} {}",
+        game_state.selected_node_index,
+        truncate_text(
+            &game_state.selected_node_name().unwrap_or_default(),
+            300.,
+            label_size
+        )
+    );
+    let node_y = 35.0 + label_dimensions.height + label_padding;
+    draw_text(&node_text, 10.0, node_y, label_size as f32, WHITE);
+    if game_resources.node_view
+        && let Some(node) = game_resources.nodes.get(game_state.selected_node_index)
+        && let Some(name) = node.metadata.name.as_deref()
+        && let Some(resources) = node_resources(node, &game_resources.pods_on_node(name))
+    {
+        let node_width = measure_text(&node_text, None, label_size, label_scale).width;
+        draw_text(&resources, 10.0 + node_width + 20., node_y, 18., LIGHTGRAY);
+    }
+
+    if !game_resources.node_view {
+        draw_text(
+            "You don't have permission to list nodes; node view disabled",
+            10.0,
+            35.0 + (label_dimensions.height + label_padding) * 3. + 18.,
+            16.,
+            ORANGE,
+        );
+    }
+
+    if game_resources.clock_skewed {
+        let hint = "clock skew detected, ages may be off";
+        draw_text(
+            hint,
+            10.0,
+            35.0 + (label_dimensions.height + label_padding) * 3.,
+            16.,
+            GRAY,
+        );
+    }
+
+    if game_state.paused {
+        let ribbon = "PAUSED - economy frozen";
+        let ribbon_size = 18;
+        let ribbon_dimensions = measure_text(ribbon, None, ribbon_size, 1.);
+        let ribbon_width = credits_width.max(ribbon_dimensions.width) + 16.;
+        draw_rectangle(
+            4.,
+            credits_y - label_dimensions.height - 2.,
+            ribbon_width,
+            label_dimensions.height + 8.,
+            Color::new(0.8, 0.5, 0., 0.85),
+        );
+        draw_text(
+            ribbon,
+            4. + ribbon_width / 2. - ribbon_dimensions.width / 2.,
+            credits_y,
+            ribbon_size as f32,
+            BLACK,
+        );
+    }
+}
+
+pub fn draw_node() {
+    let width = screen_width();
+    let height = screen_height();
+    let node_index = storage::get::<GameState>().selected_node_index;
+    let game_resources = storage::get::<GameResources>();
+    // e.g. before the first node watch synced
+    let Some(node) = game_resources.nodes.get(node_index) else {
+        storage::store(HitRects::default());
+        draw_no_nodes();
+        return;
+    };
+    let node_name = node.metadata.name.as_deref().unwrap_or("<unnamed>");
+    let pods = game_resources.pods_on_node(node_name);
+
+    // draw node plane
+    let node_width = width * 0.7;
+    let node_height = NODE_PLANE_HEIGHT;
+    let node_color = node_load(node, &pods).map(load_color).unwrap_or(WHITE);
+    let mut hit_rects = HitRects::default();
+    hit_rects.nodes.push((
+        node_index,
+        Rect::new(
+            width / 2. - node_width / 2.,
+            height - node_height / 2.,
+            node_width,
+            node_height,
+        ),
+    ));
+    draw_box(
+        width / 2. - node_width / 2.,
+        height - node_height / 2.,
+        node_width,
+        node_height,
+        20.,
+        node_color,
+    );
+
+    let settings = storage::get::<Settings>();
+    if settings.node_labels {
+        draw_rectangle_lines(
+            width / 2. - node_width / 2.,
+            height - node_height / 2.,
+            node_width,
+            node_height,
+            2.,
+            WHITE,
+        );
+        let label = truncate_text(node_name, node_width - 16., 20);
+        let label_width = measure_text(&label, None, 20, 1.).width;
+        draw_text(
+            &label,
+            width / 2. - label_width / 2.,
+            height - node_height / 2. + 20.,
+            20.,
+            BLACK,
+        );
+    }
+
+    let game_state = storage::get::<GameState>();
+    if settings.node_budget && !game_state.is_node_unlocked(node_index, node_name) {
+        let text = format!("LOCKED - [U]nlock for ${}", settings.node_unlock_price);
+        let dim = measure_text(&text, None, 24, 1.);
+        draw_rectangle(
+            width / 2. - node_width / 2.,
+            height - node_height / 2.,
+            node_width,
+            node_height,
+            Color::new(0., 0., 0., 0.6),
+        );
+        draw_text(
+            &text,
+            width / 2. - dim.width / 2.,
+            height - node_height / 2. + dim.height + 8.,
+            24.,
+            WHITE,
+        );
+    }
+
+    // outline the selected unit
+    let offset = game_state.unit_scroll_offset;
+    if matches!(game_state.navigation_mode, NavigationMode::Node)
+        && let Some(p) = pods.get(game_state.selected_unit_index)
+        && let Some(slot) = unit_slot(game_state.selected_unit_index, offset)
+    {
+        let is_miner = get_unit_type(p).as_deref() == Some("miner");
+        let pos = unit_position(slot, is_miner);
+        let half = UNIT_SIZE / 2. + 6.;
+        draw_rectangle_lines(pos.x - half, pos.y - half, half * 2., half * 2., 3., YELLOW);
+    }
+
+    draw_links(&pods, offset);
+
+    // draw pods info
+    let per_page = units_per_page();
+    for (slot, p) in pods.iter().skip(offset).take(per_page).enumerate() {
+        let is_miner = get_unit_type(p).as_deref() == Some("miner");
+        let pos = unit_position(slot, is_miner);
+        hit_rects.units.push((
+            offset + slot,
+            Rect::new(
+                pos.x - UNIT_SIZE / 2.,
+                pos.y - UNIT_SIZE / 2.,
+                UNIT_SIZE,
+                UNIT_SIZE,
+            ),
+        ));
+        match get_unit_type(p).as_deref() {
+            Some("miner") => {
+                draw_miner(p, pos.x, pos.y, UNIT_SIZE, phase_color(p));
+                draw_provenance(p, pos.x, pos.y, UNIT_SIZE);
+            }
+            unit_type => {
+                draw_processor(p, pos.x, pos.y, UNIT_SIZE, phase_color(p));
+                if unit_type == Some("processor") {
+                    draw_occupancy(
+                        game_resources.miners_targeting(p),
+                        game_resources.processor_capacity,
+                        pos,
+                    );
+                }
+                if settings.advanced_unit.as_ref().is_some_and(|a| a.owns(p)) {
+                    let half = UNIT_SIZE / 2. + 2.;
+                    draw_rectangle_lines(
+                        pos.x - half,
+                        pos.y - half,
+                        half * 2.,
+                        half * 2.,
+                        2.,
+                        ORANGE,
+                    );
+                }
+                if unit_type.is_some() {
+                    draw_provenance(p, pos.x, pos.y, UNIT_SIZE);
+                }
+            }
+        }
+    }
+
+    // preview the link of the miner being created to its picked processor
+    if matches!(game_state.navigation_mode, NavigationMode::Create)
+        && matches!(game_state.create_target, Some(CreateTarget::Miner))
+        && let Some(selection) = game_state.create_target_selection
+        && let Some(processor) = game_resources.processors().get(selection)
+    {
+        let new_miner = unit_position(pods.len().saturating_sub(offset).min(per_page), true);
+        draw_rectangle_lines(
+            new_miner.x - UNIT_SIZE / 2.,
+            new_miner.y - UNIT_SIZE / 2.,
+            UNIT_SIZE,
+            UNIT_SIZE,
+            2.,
+            YELLOW,
+        );
+        let occupancy = format!("{}/{}", processor.miners, processor.capacity);
+        let index = pods
+            .iter()
+            .position(|p| p.metadata.name.as_deref() == Some(processor.name));
+        match index.and_then(|i| unit_slot(i, offset)) {
+            Some(slot) => {
+                let target = unit_position(slot, false);
+                draw_line(new_miner.x, new_miner.y, target.x, target.y, 2., YELLOW);
+                let outline = UNIT_SIZE + 12.;
+                draw_rectangle_lines(
+                    target.x - outline / 2.,
+                    target.y - outline / 2.,
+                    outline,
+                    outline,
+                    4.,
+                    YELLOW,
+                );
+                draw_text(
+                    &occupancy,
+                    target.x - UNIT_SIZE / 2.,
+                    target.y - outline,
+                    20.,
+                    YELLOW,
+                );
+            }
+            None => {
+                // the processor is on another node or scrolled away, point
+                // off screen
+                let target = vec2(width - 10., new_miner.y - 120.);
+                draw_line(new_miner.x, new_miner.y, target.x, target.y, 2., YELLOW);
+                let whereabouts = if index.is_some() {
+                    "off screen"
+                } else {
+                    "on another node"
+                };
+                draw_text(
+                    &format!("{} {whereabouts} ({occupancy})", processor.name),
+                    new_miner.x,
+                    target.y - 8.,
+                    18.,
+                    YELLOW,
+                );
+            }
+        }
+    }
+
+    // tell how many units are scrolled away on each side
+    let y = height - NODE_PLANE_HEIGHT / 2. - 80.;
+    if offset > 0 {
+        draw_text(&format!("← {offset} more"), 10., y, 20., WHITE);
+    }
+    let after = pods.len().saturating_sub(offset + per_page);
+    if after > 0 {
+        let text = format!("{after} more →");
+        let dim = measure_text(&text, None, 20, 1.);
+        draw_text(&text, width - dim.width - 10., y, 20., WHITE);
+    }
+    // draw_text(&format!("{}", pods.len()), 0., height - 10., 18., WHITE);
+    storage::store(hit_rects);
+}
+
+/// Number of node cards fitting across the screen in [`draw_cluster`]
+pub fn nodes_per_page() -> usize {
+    ((screen_width() - NODE_CARD_GAP) / (NODE_CARD_SIZE.x + NODE_CARD_GAP)).max(1.) as usize
+}
+
+/// Every node as a card with its units in miniature, paged so the selected
+/// node is shown
+pub fn draw_cluster() {
+    let game_state = storage::get::<GameState>();
+    let game_resources = storage::get::<GameResources>();
+    let settings = storage::get::<Settings>();
+    let mut hit_rects = HitRects::default();
+
+    let nodes_len = game_resources.nodes.len();
+    if nodes_len == 0 {
+        draw_no_nodes();
+    }
+    let per_page = nodes_per_page();
+    let page_start = game_state.selected_node_index / per_page * per_page;
+    let shown = nodes_len.saturating_sub(page_start).min(per_page);
+    let total_width = shown as f32 * (NODE_CARD_SIZE.x + NODE_CARD_GAP) - NODE_CARD_GAP;
+    let start_x = screen_width() / 2. - total_width / 2.;
+    let y = screen_height() / 2. - NODE_CARD_SIZE.y / 2.;
+    let plane_height = 30.;
+    let mini_gap = MINI_UNIT_SIZE + 6.;
+    let per_row = ((NODE_CARD_SIZE.x - 12.) / mini_gap) as usize;
+    let rows = ((NODE_CARD_SIZE.y - plane_height - 36.) / mini_gap) as usize;
+
+    for (i, node) in game_resources
+        .nodes
+        .iter()
+        .enumerate()
+        .skip(page_start)
+        .take(per_page)
+    {
+        let x = start_x + (i - page_start) as f32 * (NODE_CARD_SIZE.x + NODE_CARD_GAP);
+        let node_name = node.metadata.name.as_deref().unwrap_or_default();
+        let pods = game_resources.pods_on_node(node_name);
+        hit_rects
+            .nodes
+            .push((i, Rect::new(x, y, NODE_CARD_SIZE.x, NODE_CARD_SIZE.y)));
+
+        draw_rectangle(
+            x,
+            y,
+            NODE_CARD_SIZE.x,
+            NODE_CARD_SIZE.y,
+            Color::new(0.1, 0.1, 0.1, 1.),
+        );
+        let node_color = node_load(node, &pods).map(load_color).unwrap_or(WHITE);
+        draw_box(
+            x,
+            y + NODE_CARD_SIZE.y - plane_height,
+            NODE_CARD_SIZE.x,
+            plane_height,
+            8.,
+            node_color,
+        );
+        draw_text(
+            &truncate_text(node_name, NODE_CARD_SIZE.x - 12., 18),
+            x + 6.,
+            y + 20.,
+            18.,
+            WHITE,
+        );
+
+        // units fill the card row by row, in the order of the node view
+        for (j, p) in pods.iter().enumerate().take(per_row * rows) {
+            let center = vec2(
+                x + 6. + mini_gap * (j % per_row) as f32 + MINI_UNIT_SIZE / 2.,
+                y + 36. + mini_gap * (j / per_row) as f32 + MINI_UNIT_SIZE / 2.,
+            );
+            draw_mini_unit(
+                center,
+                get_unit_type(p).as_deref() == Some("miner"),
+                phase_color(p),
+            );
+            if i == game_state.selected_node_index {
+                hit_rects.units.push((
+                    j,
+                    Rect::new(
+                        center.x - MINI_UNIT_SIZE / 2.,
+                        center.y - MINI_UNIT_SIZE / 2.,
+                        MINI_UNIT_SIZE,
+                        MINI_UNIT_SIZE,
+                    ),
+                ));
+            }
+        }
+        let hidden = pods.len().saturating_sub(per_row * rows);
+        if hidden > 0 {
+            draw_text(
+                &format!("+{hidden}"),
+                x + NODE_CARD_SIZE.x - 40.,
+                y + 20.,
+                18.,
+                GRAY,
+            );
+        }
+
+        if settings.node_budget && !game_state.is_node_unlocked(i, node_name) {
+            draw_rectangle(
+                x,
+                y,
+                NODE_CARD_SIZE.x,
+                NODE_CARD_SIZE.y,
+                Color::new(0., 0., 0., 0.6),
+            );
+            let dim = measure_text("LOCKED", None, 20, 1.);
+            draw_text(
+                "LOCKED",
+                x + NODE_CARD_SIZE.x / 2. - dim.width / 2.,
+                y + NODE_CARD_SIZE.y / 2.,
+                20.,
+                WHITE,
+            );
+        }
+        if i == game_state.selected_node_index {
+            draw_rectangle_lines(
+                x - 4.,
+                y - 4.,
+                NODE_CARD_SIZE.x + 8.,
+                NODE_CARD_SIZE.y + 8.,
+                3.,
+                YELLOW,
+            );
+        }
+    }
+
+    // tell how many nodes are paged away on each side
+    let text_y = y - 20.;
+    if page_start > 0 {
+        draw_text(&format!("← {page_start} more"), 10., text_y, 20., WHITE);
+    }
+    let after = nodes_len.saturating_sub(page_start + per_page);
+    if after > 0 {
+        let text = format!("{after} more →");
+        let dim = measure_text(&text, None, 20, 1.);
+        draw_text(&text, screen_width() - dim.width - 10., text_y, 20., WHITE);
+    }
+    storage::store(hit_rects);
+}
+
+fn draw_no_nodes() {
+    let text = "No nodes available";
+    let dim = measure_text(text, None, 30, 1.);
+    draw_text(
+        text,
+        screen_width() / 2. - dim.width / 2.,
+        screen_height() / 2.,
+        30.,
+        GRAY,
+    );
+}
+
+/// Strip above the nodes with the units waiting for the scheduler, a unit
+/// moves down to its node once it's placed
+pub fn draw_staging_area() {
+    let game_resources = storage::get::<GameResources>();
+    let hit_rects = storage::get::<HitRects>();
+    let mut landings = storage::get_mut::<Landings>();
+    let now = get_time();
+
+    let pods = game_resources.unscheduled_pods();
+    let gap = MINI_UNIT_SIZE * 2.;
+    let start_x = screen_width() / 2. - gap * pods.len().saturating_sub(1) as f32 / 2.;
+    let y = STAGING_TOP + 34.;
+    let staged = pods
+        .iter()
+        .enumerate()
+        .filter_map(|(i, p)| Some((p.metadata.name.clone()?, vec2(start_x + gap * i as f32, y))))
+        .collect::<HashMap<_, _>>();
+
+    // units that left the staging area since last frame
+    let landed = std::mem::take(&mut landings.staged)
+        .into_iter()
+        .filter(|(name, _)| !staged.contains_key(name))
+        .filter_map(|(name, from)| {
+            let p = game_resources
+                .pods
+                .iter()
+                .find(|p| p.metadata.name.as_ref() == Some(&name))?;
+            let node_name = p.spec.as_ref()?.node_name.as_deref()?;
+            // the node card or plane when it's on screen, straight down otherwise
+            let to = hit_rects
+                .nodes
+                .iter()
+                .find(|(i, _)| {
+                    game_resources
+                        .nodes
+                        .get(*i)
+                        .and_then(|n| n.metadata.name.as_deref())
+                        == Some(node_name)
+                })
+                .map(|(_, rect)| rect.center())
+                .unwrap_or(vec2(from.x, screen_height()));
+            Some(Landing {
+                is_miner: get_unit_type(p).as_deref() == Some("miner"),
+                color: phase_color(p),
+                from,
+                to,
+                started: now,
+            })
+        })
+        .collect::<Vec<_>>();
+    landings.flights.extend(landed);
+    landings
+        .flights
+        .retain(|f| now - f.started < LANDING_SECONDS);
+
+    if !pods.is_empty() {
+        let text = format!("Unscheduled: {}", pods.len());
+        let dim = measure_text(&text, None, 18, 1.);
+        let width = (gap * pods.len() as f32).max(dim.width) + 20.;
+        draw_rectangle(
+            screen_width() / 2. - width / 2.,
+            STAGING_TOP,
+            width,
+            48.,
+            Color::new(0.1, 0.1, 0.1, 0.9),
+        );
+        draw_text(
+            &text,
+            screen_width() / 2. - dim.width / 2.,
+            STAGING_TOP + 18.,
+            18.,
+            GRAY,
+        );
+        for p in &pods {
+            if let Some(pos) = p.metadata.name.as_ref().and_then(|n| staged.get(n)) {
+                draw_mini_unit(
+                    *pos,
+                    get_unit_type(p).as_deref() == Some("miner"),
+                    phase_color(p),
+                );
+            }
+        }
+    }
+    for flight in &landings.flights {
+        let t = ((now - flight.started) / LANDING_SECONDS) as f32;
+        draw_mini_unit(
+            flight.from.lerp(flight.to, t),
+            flight.is_miner,
+            flight.color,
+        );
+    }
+    landings.staged = staged;
+}
+
+/// A unit in miniature, miners are round and processors square
+fn draw_mini_unit(center: Vec2, is_miner: bool, color: Color) {
+    if is_miner {
+        draw_circle(center.x, center.y, MINI_UNIT_SIZE / 2., color);
+    } else {
+        draw_rectangle(
+            center.x - MINI_UNIT_SIZE / 2.,
+            center.y - MINI_UNIT_SIZE / 2.,
+            MINI_UNIT_SIZE,
+            MINI_UNIT_SIZE,
+            color,
+        );
+    }
+}
+
+/// Lines from the shown miners to the processors they target, or a stub with
+/// the target when the processor isn't shown
+fn draw_links(pods: &[&Pod], offset: usize) {
+    let color = Color::new(0.4, 0.8, 1., 0.8);
+    for (i, miner) in pods.iter().enumerate() {
+        if get_unit_type(miner).as_deref() != Some("miner") {
+            continue;
+        }
+        let (Some(slot), Some(target)) = (unit_slot(i, offset), get_unit_target(miner)) else {
+            continue;
+        };
+        let key = target_key(&target);
+        let from = unit_position(slot, true);
+        let processor = pods.iter().position(|p| {
+            get_unit_type(p).as_deref() == Some("processor") && processor_targets(p).contains(&key)
+        });
+        match processor.and_then(|j| unit_slot(j, offset)) {
+            Some(slot) => {
+                let to = unit_position(slot, false);
+                draw_line(from.x, from.y, to.x, to.y, 1., color);
+            }
+            None => {
+                let tip = vec2(from.x + UNIT_SIZE * 0.8, from.y - UNIT_SIZE * 0.8);
+                draw_line(from.x, from.y, tip.x, tip.y, 1., color);
+                draw_triangle(tip, tip + vec2(-8., 2.), tip + vec2(-2., 8.), color);
+                draw_text(&target, tip.x + 4., tip.y, 14., color);
+            }
+        }
+    }
+}
+
+/// Number of unit slots fitting between the first slot and the right edge of
+/// the screen
+pub fn units_per_page() -> usize {
+    let gap = UNIT_SIZE * 3.;
+    let room = screen_width() - UNIT_SIZE - unit_position(0, true).x;
+    (room / gap).max(0.) as usize + 1
+}
+
+/// Slot of the unit at `index` on the node plane scrolled by `offset`, `None`
+/// when it's scrolled out of view
+pub fn unit_slot(index: usize, offset: usize) -> Option<usize> {
+    index
+        .checked_sub(offset)
+        .filter(|&slot| slot < units_per_page())
+}
+
+/// Screen position of the unit drawn in `slot` of the node plane, processors
+/// hover above the plane
+pub fn unit_position(slot: usize, is_miner: bool) -> Vec2 {
+    let gap = UNIT_SIZE * 3.;
+    let x = screen_width() / 2. - 200. + gap * slot as f32;
+    let y = screen_height() - NODE_PLANE_HEIGHT / 2. + 15. - UNIT_SIZE / 2.;
+    if is_miner {
+        vec2(x, y)
+    } else {
+        vec2(x, y - 48.)
+    }
+}
+
+/// Requests of the pods on `node` against its allocatable CPU and memory, e.g.
+/// `CPU: 0.5 / 4, Mem: 1.0Gi / 8.0Gi`
+fn node_resources(node: &Node, pods: &[&Pod]) -> Option<String> {
+    let allocatable = node.status.as_ref()?.allocatable.as_ref()?;
+    let usage = |resource: &str| {
+        let allocatable = parse_quantity(&allocatable.get(resource)?.0)?;
+        let requested = pods.iter().map(|p| pod_request(p, resource)).sum::<f64>();
+        Some((requested, allocatable))
+    };
+    let (cpu_requested, cpu) = usage("cpu")?;
+    let (mem_requested, mem) = usage("memory")?;
+    Some(format!(
+        "CPU: {} / {}, Mem: {} / {}",
+        format_cores(cpu_requested),
+        format_cores(cpu),
+        format_bytes(mem_requested),
+        format_bytes(mem)
+    ))
+}
+
+fn format_cores(cores: f64) -> String {
+    if cores.fract() == 0. {
+        format!("{cores}")
+    } else {
+        format!("{cores:.1}")
+    }
+}
+
+fn format_bytes(bytes: f64) -> String {
+    let gib = 1024f64.powi(3);
+    if bytes >= gib {
+        format!("{:.1}Gi", bytes / gib)
+    } else {
+        format!("{:.0}Mi", bytes / 1024f64.powi(2))
+    }
+}
+
+/// How full a node is, by the requests of its pods or else by their count
+/// against the pods the node takes
+fn node_fill(node: &Node, pods: &[&Pod]) -> Option<f32> {
+    node_load(node, pods).or_else(|| {
+        let max_pods = node.status.as_ref()?.allocatable.as_ref()?.get("pods")?;
+        let max_pods = parse_quantity(&max_pods.0)?;
+        (max_pods > 0.).then(|| (pods.len() as f64 / max_pods) as f32)
+    })
+}
+
+/// Every node as a cell in the top right corner colored by how full it is,
+/// drawn over the cluster and node views
+pub fn draw_minimap() {
+    let game_state = storage::get::<GameState>();
+    let game_resources = storage::get::<GameResources>();
+    let mut hit_rects = storage::get_mut::<HitRects>();
+    hit_rects.minimap.clear();
+    if game_resources.nodes.is_empty() {
+        return;
+    }
+
+    let columns = MINIMAP_COLUMNS.min(game_resources.nodes.len());
+    let pitch = MINIMAP_CELL_SIZE + 2.;
+    let x = screen_width() - columns as f32 * pitch - 10.;
+    let y = 50.;
+    for (i, node) in game_resources.nodes.iter().enumerate() {
+        let cell = Rect::new(
+            x + (i % MINIMAP_COLUMNS) as f32 * pitch,
+            y + (i / MINIMAP_COLUMNS) as f32 * pitch,
+            MINIMAP_CELL_SIZE,
+            MINIMAP_CELL_SIZE,
+        );
+        let node_name = node.metadata.name.as_deref().unwrap_or_default();
+        let color = node_fill(node, &game_resources.pods_on_node(node_name))
+            .map(load_color)
+            .unwrap_or(DARKGRAY);
+        draw_rectangle(cell.x, cell.y, cell.w, cell.h, color);
+        if i == game_state.selected_node_index {
+            draw_rectangle_lines(
+                cell.x - 2.,
+                cell.y - 2.,
+                cell.w + 4.,
+                cell.h + 4.,
+                2.,
+                YELLOW,
+            );
+        }
+        hit_rects.minimap.push((i, cell));
+    }
+}
+
+/// Blue -> red gradient for a node load in `0.0..=1.0`
+fn load_color(load: f32) -> Color {
+    let t = load.clamp(0., 1.);
+    Color::new(t, 0.2, 1. - t, 1.)
+}
+
+/// Who created a game-typed pod, told apart by its session id
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Provenance {
+    ThisSession,
+    PriorSession,
+    /// Labeled as a unit outside the game, without a session id
+    External,
+}
+
+impl Provenance {
+    pub fn of(p: &Pod) -> Self {
+        match get_session_id(p) {
+            Some(id) if id == SESSION_ID.as_str() => Self::ThisSession,
+            Some(_) => Self::PriorSession,
+            None => Self::External,
+        }
+    }
+}
+
+/// Units of this session are drawn as is, units from a prior session get a
+/// thin outline and external ones only corner marks
+fn draw_provenance(p: &Pod, x: f32, y: f32, size: f32) {
+    let half = size / 2. + 4.;
+    match Provenance::of(p) {
+        Provenance::ThisSession => {}
+        Provenance::PriorSession => {
+            draw_rectangle_lines(x - half, y - half, half * 2., half * 2., 1., GRAY);
+        }
+        Provenance::External => {
+            let mark = size / 4.;
+            for (cx, dx) in [(x - half, 1.), (x + half, -1.)] {
+                for (cy, dy) in [(y - half, 1.), (y + half, -1.)] {
+                    draw_line(cx, cy, cx + mark * dx, cy, 1., GRAY);
+                    draw_line(cx, cy, cx, cy + mark * dy, 1., GRAY);
+                }
+            }
+        }
+    }
+}
+
+/// Fill color of a unit by its pod phase, so broken units stand out and
+/// units waiting for room on a node or their image show apart from ones about
+/// to start
+fn phase_color(p: &Pod) -> Color {
+    if is_unschedulable(p) {
+        return ORANGE;
+    }
+    if is_image_pull_failing(p) {
+        return MAGENTA;
+    }
+    match p.status.as_ref().and_then(|s| s.phase.as_deref()) {
+        Some("Running") => GREEN,
+        Some("Pending") => YELLOW,
+        Some("Failed" | "Unknown") => RED,
+        // `Succeeded` or no status yet
+        _ => DARKGRAY,
+    }
+}
+
+/// Miners of a processor against its capacity above it, a full processor is
+/// tinted red since more miners on it earn nothing
+fn draw_occupancy(miners: usize, capacity: usize, pos: Vec2) {
+    let full = miners >= capacity;
+    if full {
+        draw_rectangle(
+            pos.x - UNIT_SIZE / 2.,
+            pos.y - UNIT_SIZE / 2.,
+            UNIT_SIZE,
+            UNIT_SIZE,
+            Color::new(1., 0., 0., 0.4),
+        );
+    }
+    let text = format!("{miners}/{capacity}");
+    let dim = measure_text(&text, None, 16, 1.);
+    draw_text(
+        &text,
+        pos.x - dim.width / 2.,
+        pos.y - UNIT_SIZE / 2. - 8.,
+        16.,
+        if full { RED } else { WHITE },
+    );
+}
+
+fn draw_miner(pod: &Pod, x: f32, y: f32, size: f32, color: Color) {
+    if let Some(texture) = storage::get::<TextureRegistry>().get("miner") {
+        draw_texture_ex(
+            texture,
+            x - size / 2.0,
+            y - size / 2.0,
+            color,
+            DrawTextureParams {
+                dest_size: Some(vec2(size, size)),
+                ..Default::default()
+            },
+        );
+    } else {
+        // Main body (simple rectangle or custom polygon)
+        draw_box(
+            x - size / 2.0,
+            y - size / 2.0,
+            size,
+            size,
+            size / 4.0,
+            color,
+        );
+
+        // a small "engine" or "sensor" part
+        draw_triangle(
+            vec2(x - size / 4.0, y + size / 2.0),
+            vec2(x + size / 4.0, y + size / 2.0),
+            vec2(x, y + size / 2.0 + size / 4.0),
+            GRAY,
+        );
+    }
+
+    let misconfigured = pod.metadata.name.as_ref().is_some_and(|name| {
+        storage::get::<GameResources>()
+            .misconfigured_miners
+            .contains_key(name)
+    });
+    if misconfigured {
+        // warning badge on the top right corner
+        let badge_x = x + size / 2.0;
+        let badge_y = y - size / 2.0;
+        draw_triangle(
+            vec2(badge_x, badge_y - 10.),
+            vec2(badge_x - 9., badge_y + 6.),
+            vec2(badge_x + 9., badge_y + 6.),
+            YELLOW,
+        );
+        draw_text("!", badge_x - 2., badge_y + 5., 16., BLACK);
+    }
+
+    draw_restart_badge(pod, x, y, size);
+    if let Some(label) = unit_label(pod) {
+        draw_text(&label, x - size / 2.0, y, 18., WHITE);
+    }
+}
+
+fn draw_processor(pod: &Pod, x: f32, y: f32, size: f32, color: Color) {
+    if let Some(texture) = storage::get::<TextureRegistry>().get("processor") {
+        draw_texture_ex(
+            texture,
+            x - size / 2.0,
+            y - size / 2.0,
+            color,
+            DrawTextureParams {
+                dest_size: Some(vec2(size, size)),
+                ..Default::default()
+            },
+        );
+    } else {
+        // Main body (simple rectangle or custom polygon)
+        draw_box(
+            x - size / 2.0,
+            y - size / 2.0,
+            size,
+            size,
+            size / 4.0,
+            color,
+        );
+
+        // a small "engine" or "sensor" part
+        draw_triangle(
+            vec2(x - size / 4.0, y - size / 2.0),
+            vec2(x + size / 4.0, y - size / 2.0),
+            vec2(x, y - size / 2.0 - size / 4.0),
+            GRAY,
+        );
+    }
+
+    draw_restart_badge(pod, x, y, size);
+    if let Some(label) = unit_label(pod) {
+        draw_text(&label, x - size / 2.0, y, 18., WHITE);
+    }
+}
+
+/// Circular arrow with the restart count on the top left corner of a unit
+/// that restarted more than [`RESTART_BADGE_THRESHOLD`] times, e.g. a
+/// crash-looping processor
+fn draw_restart_badge(pod: &Pod, x: f32, y: f32, size: f32) {
+    let restarts = restart_count(pod);
+    if restarts <= RESTART_BADGE_THRESHOLD {
+        return;
+    }
+    let center = vec2(x - size / 2.0, y - size / 2.0);
+    let radius = 6.;
+    draw_circle(center.x, center.y, radius + 3., Color::new(0., 0., 0., 0.7));
+    draw_arc(center.x, center.y, 16, radius, 30., 1.5, 270., ORANGE);
+    // arrow head at the end of the arc
+    let tip = center + vec2(radius, 0.);
+    draw_triangle(
+        tip + vec2(-3., -1.),
+        tip + vec2(3., -1.),
+        tip + vec2(0., 3.),
+        ORANGE,
+    );
+    draw_text(
+        &restarts.to_string(),
+        center.x + radius + 4.,
+        center.y + 5.,
+        16.,
+        ORANGE,
+    );
+}
+
+/// Sprite label of a unit according to `Settings::unit_label`
+pub fn unit_label(pod: &Pod) -> Option<String> {
+    match storage::get::<Settings>().unit_label {
+        UnitLabel::Ip => get_unit_ip(pod).map(str::to_string),
+        UnitLabel::Name => pod
+            .metadata
+            .name
+            .as_deref()
+            .map(|name| truncate_text(name, UNIT_SIZE * 3. - 8., 18)),
+        UnitLabel::Age => unit_age(pod, Utc::now()).map(format_age),
+        UnitLabel::None => None,
+    }
+}
+
+pub fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+/// Shorten `text` with an ellipsis so it fits in `max_width` when drawn with
+/// `font_size`
+fn truncate_text(text: &str, max_width: f32, font_size: u16) -> String {
+    if measure_text(text, None, font_size, 1.).width <= max_width {
+        return text.to_string();
+    }
+    let mut truncated = text.to_string();
+    while !truncated.is_empty() {
+        truncated.pop();
+        let candidate = format!("{truncated}...");
+        if measure_text(&candidate, None, font_size, 1.).width <= max_width {
+            return candidate;
+        }
+    }
+    "...".to_string()
+}
+
+pub fn draw_navbar() {
+    let width = screen_width();
+    let height = screen_height();
+    let navigation_mode = storage::get::<GameState>().navigation_mode.clone();
+    let keymap = storage::get::<KeyMap>();
+    let key = |action| keymap.key_name(action);
+    let nav_keys = format!("[{} {}]", key(Action::NavLeft), key(Action::NavRight));
+
+    let label_font_size = 18;
+    let label_dim = measure_text("Cluster", None, label_font_size, 1.);
+    let padding = 4.;
+
+    // draw navbar background
+    draw_rectangle(
+        0.,
+        height - padding * 2. - label_dim.height - 5.,
+        width,
+        padding * 2. + label_dim.height + 10.,
+        GRAY,
+    );
+
+    // draw tooltip
+    let mut tooltip = String::with_capacity(0x50);
+    // build options drawn after the tooltip, colored by affordability
+    let mut priced_options = Vec::new();
+    let pending_conversion = storage::get::<GameState>().pending_conversion.clone();
+    let pending_force_delete = storage::get::<GameState>().pending_force_delete.clone();
+    let pending_delete = storage::get::<GameState>().pending_delete.clone();
+    let pending_bulk_delete = storage::get::<GameState>().pending_bulk_delete.clone();
+    let node_search = storage::get::<GameState>().node_search.clone();
+    if let Some(search) = node_search {
+        tooltip.push_str("Find node : ");
+        tooltip.push_str(&search.query);
+        if search
+            .first_match(&storage::get::<GameResources>())
+            .is_none()
+        {
+            tooltip.push_str(" (no match)");
+        }
+        tooltip.push_str(" | [Enter] Go | [Esc] Cancel");
+    } else if let Some(name) = pending_delete {
+        tooltip.push_str(&format!("Delete {name}? [Y/N]"));
+    } else if let Some(names) = pending_bulk_delete {
+        tooltip.push_str(&format!(
+            "Delete all {} units on this node ({})? [Y/N]",
+            names.len(),
+            names.join(", ")
+        ));
+    } else if let Some(name) = pending_force_delete {
+        tooltip.push_str(&format!(
+            "FORCE delete {name}? It is removed without waiting for its node, \
+             the container may keep running [Y/N]"
+        ));
+    } else if let Some((name, target, miner_target)) = pending_conversion {
+        match target {
+            CreateTarget::Miner => tooltip.push_str(&format!(
+                "Convert {name} to miner, target : {miner_target} | [{}] Convert | [{}] Cancel",
+                keymap.key_name(Action::Select),
+                keymap.key_name(Action::Back)
+            )),
+            CreateTarget::Processor => {
+                tooltip.push_str(&format!("Convert {name} to processor? [Y/N]"));
+            }
+        }
+    } else if storage::get::<GameState>().pending_unknown_target {
+        tooltip.push_str(
+            "No processor at that target, this miner will earn nothing. Create anyway? [Y/N]",
+        );
+    } else {
+        match navigation_mode {
+            NavigationMode::Cluster => {
+                tooltip.push_str("Cluster");
+                tooltip.push_str(&format!(" | [{}] Select node", key(Action::Select)));
+                tooltip.push_str(&format!(" | {nav_keys} Switch node"));
+                tooltip.push_str(&format!(" | [{}] Create unit", key(Action::CreateUnit)));
+                tooltip.push_str(" | [S]ave/[L]oad blueprint");
+                tooltip.push_str(" | [K]ey hints");
+                tooltip.push_str(" | [V] Unit list");
+                tooltip.push_str(" | [/] Find node");
+                let game_state = storage::get::<GameState>();
+                let settings = storage::get::<Settings>();
+                if let Some(owner) = settings.owner.as_deref() {
+                    if game_state.show_all_owners {
+                        tooltip.push_str(" | [O] Only mine");
+                    } else {
+                        tooltip.push_str(&format!(" | [O] All owners (showing {owner})"));
+                    }
+                }
+                if settings.node_budget
+                    && let Some(node_name) = game_state.selected_node_name()
+                    && !game_state.is_node_unlocked(game_state.selected_node_index, &node_name)
+                {
+                    priced_options.push(("[U]nlock node".to_string(), settings.node_unlock_price));
+                }
+                tooltip.push_str(" | [Ctrl+P] Commands");
+                tooltip.push_str(" | [H]elp");
+            }
+            NavigationMode::Blueprint(action) => {
+                let game_state = storage::get::<GameState>();
+                tooltip.push_str(match action {
+                    BlueprintAction::Save => "Save   ",
+                    BlueprintAction::Load => "Load   ",
+                });
+                tooltip.push_str(&format!(" | [{}] Back", key(Action::Back)));
+                tooltip.push_str(" | Blueprint name : ");
+                tooltip.push_str(&game_state.create_text_buf);
+            }
+            NavigationMode::List => {
+                tooltip.push_str("Units  ");
+                tooltip.push_str(&format!(" | [{}] Back", key(Action::Back)));
+                tooltip.push_str(" | [Up/Down] Select");
+                tooltip.push_str(" | [1-8] Sort");
+                tooltip.push_str(&format!(" | [{}] Show on node", key(Action::Select)));
+                tooltip.push_str(&format!(" | [{}] Delete unit", key(Action::Delete)));
+            }
+            NavigationMode::Node => {
+                tooltip.push_str("Node   ");
+                tooltip.push_str(&format!(" | [{}] Back", key(Action::Back)));
+                tooltip.push_str(&format!(" | {nav_keys} Switch unit"));
+                tooltip.push_str(&format!(" | [{}] Delete unit", key(Action::Delete)));
+                tooltip.push_str(" | [L]ogs");
+            }
+            NavigationMode::Create => {
+                tooltip.push_str("Create ");
+                let game_state = storage::get::<GameState>();
+                if game_state.create_batch() > 1 {
+                    tooltip.push_str(&format!("x{} ", game_state.create_batch()));
+                }
+                match game_state.create_target.as_ref() {
+                    Some(target) if game_state.create_naming => {
+                        tooltip.push_str(" | ");
+                        tooltip.push_str(&format!("{target:?}"));
+                        tooltip.push_str(" name : ");
+                        if game_state.create_name_buf.is_empty() {
+                            tooltip.push_str("(random)");
+                        } else {
+                            tooltip.push_str(&game_state.create_name_buf);
+                        }
+                        let next = match target {
+                            CreateTarget::Miner => "Next",
+                            CreateTarget::Processor => "Build",
+                        };
+                        tooltip.push_str(&format!(
+                            " | [{}] {next} | [{}] Cancel",
+                            key(Action::Select),
+                            key(Action::Back)
+                        ));
+                    }
+                    Some(target) => {
+                        tooltip.push_str(" | ");
+                        tooltip.push_str(&format!("{target:?}"));
+                        if matches!(target, CreateTarget::Miner) {
+                            tooltip.push_str(" : ");
+                            tooltip.push_str(&game_state.create_text_buf);
+                        }
+                        tooltip.push_str(&format!(
+                            " | [{}] Build | [{}] Cancel",
+                            key(Action::Select),
+                            key(Action::Back)
+                        ));
+                    }
+                    None => {
+                        tooltip.push_str(&format!(" | [0-9] Count | [{}] Back", key(Action::Back)));
+                        priced_options.push((
+                            format!("[{}] Miner", key(Action::SelectMiner)),
+                            game_state.miner_price,
+                        ));
+                        priced_options.push((
+                            format!("[{}] Processor", key(Action::SelectProcessor)),
+                            game_state.processor_price,
+                        ));
+                        if let Some(advanced) = storage::get::<Settings>().advanced_unit.as_ref() {
+                            priced_options.push(("[A]dvanced".to_string(), advanced.price));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    let text_y = height - label_dim.height / 2. - padding;
+    let mut text_x = 0. + padding;
+    draw_text(&tooltip, text_x, text_y, 18., WHITE);
+    text_x += measure_text(&tooltip, None, label_font_size, 1.).width;
+
+    let credits = storage::get::<GameState>().credits;
+    for (label, price) in priced_options {
+        draw_text(" | ", text_x, text_y, 18., WHITE);
+        text_x += measure_text(" | ", None, label_font_size, 1.).width;
+
+        let option = format!("{label} (${price})");
+        let option_dim = measure_text(&option, None, label_font_size, 1.);
+        let color = if credits >= price {
+            draw_rectangle(
+                text_x - 2.,
+                text_y - label_dim.height - 2.,
+                option_dim.width + 4.,
+                label_dim.height + 6.,
+                Color::new(0., 0.4, 0., 1.),
+            );
+            GREEN
+        } else {
+            DARKGRAY
+        };
+        draw_text(&option, text_x, text_y, 18., color);
+        text_x += option_dim.width;
+    }
+}
+
+pub fn draw_cluster_error_banner() {
+    let game_state = storage::get::<GameState>();
+    let text = match game_state.cluster_error.as_ref() {
+        Some(err) => format!("Cluster unreachable: {err}"),
+        None => "Cluster reachable again, press space to resume".to_string(),
+    };
+    let font_size = 20;
+    let text_dimensions = measure_text(&text, None, font_size, 1.);
+    let padding = 8.;
+    let y = screen_height() / 2. + 40.;
+
+    draw_rectangle(
+        0.,
+        y - text_dimensions.height - padding,
+        screen_width(),
+        text_dimensions.height + padding * 2.,
+        RED,
+    );
+    draw_text(
+        &text,
+        screen_width() / 2. - text_dimensions.width / 2.,
+        y,
+        font_size as f32,
+        WHITE,
+    );
+}
+
+pub fn draw_command_palette() {
+    let Some(palette) = storage::get::<GameState>().palette.clone() else {
+        return;
+    };
+    let commands = palette.matches(&storage::get::<GameResources>());
+
+    let font_size = 20;
+    let padding = 8.;
+    let line_height = measure_text("Placeholder", None, font_size, 1.).height + padding;
+    let max_rows = 8;
+    let width = screen_width() * 0.5;
+    let height = line_height * (max_rows + 1) as f32 + padding * 2.;
+    let x = screen_width() / 2. - width / 2.;
+    let y = screen_height() * 0.15;
+
+    draw_rectangle(x, y, width, height, Color::new(0.1, 0.1, 0.1, 0.95));
+    draw_rectangle_lines(x, y, width, height, 2., GRAY);
+    draw_text(
+        &format!("> {}", palette.query),
+        x + padding,
+        y + padding + line_height - padding / 2.,
+        font_size as f32,
+        WHITE,
+    );
+
+    let selected = palette.selected;
+    let first = selected.saturating_sub(max_rows - 1);
+    for (row, (i, (_, label))) in commands
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take(max_rows)
+        .enumerate()
+    {
+        let row_y = y + padding + line_height * (row + 1) as f32;
+        if i == selected {
+            draw_rectangle(x, row_y, width, line_height, DARKBLUE);
+        }
+        draw_text(
+            label,
+            x + padding,
+            row_y + line_height - padding / 2.,
+            font_size as f32,
+            WHITE,
+        );
+    }
+}
+
+/// List of processors a new miner can target, shown while typing its target
+pub fn draw_target_picker() {
+    let game_state = storage::get::<GameState>();
+    if !matches!(game_state.navigation_mode, NavigationMode::Create)
+        || !matches!(game_state.create_target, Some(CreateTarget::Miner))
+        || game_state.create_naming
+    {
+        return;
+    }
+    let game_resources = storage::get::<GameResources>();
+    let processors = game_resources.processors();
+
+    let font_size = 18;
+    let padding = 6.;
+    let line_height = measure_text("Placeholder", None, font_size, 1.).height + padding;
+    let width = 360.;
+    let height = line_height * (processors.len().max(1) + 1) as f32 + padding;
+    let x = screen_width() - width - 10.;
+    let y = 10.;
+
+    draw_rectangle(x, y, width, height, Color::new(0.1, 0.1, 0.1, 0.9));
+    draw_text(
+        "[Up/Down] Pick processor, or type a target",
+        x + padding,
+        y + line_height,
+        font_size as f32,
+        GRAY,
+    );
+    if processors.is_empty() {
+        draw_text(
+            "No processors yet",
+            x + padding,
+            y + line_height * 2.,
+            font_size as f32,
+            WHITE,
+        );
+    }
+    for (i, processor) in processors.iter().enumerate() {
+        let row_y = y + line_height * (i + 1) as f32 + padding / 2.;
+        if game_state.create_target_selection == Some(i) {
+            draw_rectangle(x, row_y, width, line_height, DARKBLUE);
+        }
+        let free = processor.free_capacity();
+        draw_text(
+            &format!(
+                "{} {} ({free}/{} free)",
+                processor.name,
+                processor.ips.join(", "),
+                processor.capacity
+            ),
+            x + padding,
+            row_y + line_height - padding / 2.,
+            font_size as f32,
+            if free == 0 { RED } else { WHITE },
+        );
+    }
+}
+
+/// Overlay explaining the economy, the unit types and the controls, toggled
+/// with [H]
+pub fn draw_help() {
+    if !storage::get::<GameState>().help {
+        return;
+    }
+    let game_config = storage::get::<GameConfig>();
+    let keymap = storage::get::<KeyMap>();
+    let key = |action| keymap.key_name(action);
+    let nav_keys = format!("[{} {}]", key(Action::NavLeft), key(Action::NavRight));
+    let sections = [
+        (
+            "Economy",
+            vec![
+                format!(
+                    "Every {}s each Miner targeting a Processor with room earns a credit.",
+                    game_config.earn_interval_seconds
+                ),
+                format!(
+                    "A Processor takes up to {} Miners, the others earn nothing.",
+                    game_config.processor_capacity
+                ),
+                format!(
+                    "Every {}s each Miner costs {} and each Processor {} credits of upkeep.",
+                    game_config.consume_interval_seconds,
+                    game_config.miner_upkeep,
+                    game_config.processor_upkeep
+                ),
+                format!(
+                    "Broke for {}s with units running, the game is over.",
+                    game_config.game_over_grace_seconds
+                ),
+            ],
+        ),
+        (
+            "Units",
+            vec![
+                "Miner: mines for the Processor at its target IP or name.".to_string(),
+                "Processor: turns the work of its Miners into credits.".to_string(),
+            ],
+        ),
+        (
+            "Cluster",
+            vec![
+                format!(
+                    "{nav_keys} Switch node | [{}] Select node | [/] Find node",
+                    key(Action::Select)
+                ),
+                format!(
+                    "[{}] Create unit | [S]ave/[L]oad blueprint | [V] Unit list",
+                    key(Action::CreateUnit)
+                ),
+                "[K]ey hints | [U]nlock node | [O]wner filter | [Ctrl+P] Commands".to_string(),
+            ],
+        ),
+        (
+            "Node",
+            vec![format!(
+                "{nav_keys} Switch unit | [{delete}] Delete unit | [Shift+{delete}] Delete all \
+                 | [L]ogs | [{}] Back",
+                key(Action::Back),
+                delete = key(Action::Delete),
+            )],
+        ),
+        (
+            "Create",
+            vec![
+                format!(
+                    "[{}] Miner | [{}] Processor, then [{}] Build | [{}] Cancel",
+                    key(Action::SelectMiner),
+                    key(Action::SelectProcessor),
+                    key(Action::Select),
+                    key(Action::Back)
+                ),
+                "Miners take a target typed in or picked with [Up/Down].".to_string(),
+            ],
+        ),
+        (
+            "Anytime",
+            vec!["[Tab] Pause | [H]elp | Click to select nodes and units".to_string()],
+        ),
+    ];
+
+    draw_rectangle(
+        0.,
+        0.,
+        screen_width(),
+        screen_height(),
+        Color::new(0., 0., 0., 0.75),
+    );
+    let font_size = 20;
+    let line_height = measure_text("Placeholder", None, font_size, 1.).height + 8.;
+    let x = screen_width() * 0.15;
+    let mut y = screen_height() * 0.1;
+    draw_text("Help | [H] or [Esc] Close", x, y, 28., YELLOW);
+    for (title, lines) in sections {
+        y += line_height * 1.5;
+        draw_text(title, x, y, font_size as f32, YELLOW);
+        for line in lines {
+            y += line_height;
+            draw_text(&line, x + 20., y, font_size as f32, WHITE);
+        }
+    }
+}
+
+/// Overlay with the logs of [`GameState::logs`], keeping the latest lines
+/// that fit
+pub fn draw_logs() {
+    let game_state = storage::get::<GameState>();
+    let Some((name, logs)) = game_state.logs.as_ref() else {
+        return;
+    };
+
+    let font_size = 16;
+    let padding = 10.;
+    let line_height = measure_text("Placeholder", None, font_size, 1.).height + 4.;
+    let x = screen_width() * 0.1;
+    let y = screen_height() * 0.1;
+    let width = screen_width() * 0.8;
+    let height = screen_height() * 0.7;
+    draw_rectangle(x, y, width, height, Color::new(0., 0., 0., 0.9));
+    draw_rectangle_lines(x, y, width, height, 2., GRAY);
+    draw_text(
+        &format!("Logs of {name} | [Esc] Close"),
+        x + padding,
+        y + padding + line_height,
+        font_size as f32,
+        YELLOW,
+    );
+
+    let lines = logs.as_deref().map(|logs| logs.lines().collect::<Vec<_>>());
+    let placeholder = match lines.as_ref() {
+        None => Some("Fetching logs..."),
+        Some(lines) if lines.is_empty() => Some("No logs"),
+        Some(_) => None,
+    };
+    if let Some(placeholder) = placeholder {
+        draw_text(
+            placeholder,
+            x + padding,
+            y + padding + line_height * 3.,
+            font_size as f32,
+            GRAY,
+        );
+        return;
+    }
+    let lines = lines.unwrap_or_default();
+    // below the title and a blank line
+    let rows = (((height - padding * 2.) / line_height) as usize).saturating_sub(2);
+    let first_line = lines.len().saturating_sub(rows);
+    for (i, line) in lines[first_line..].iter().enumerate() {
+        draw_text(
+            &truncate_text(line, width - padding * 2., font_size),
+            x + padding,
+            y + padding + line_height * (i + 3) as f32,
+            font_size as f32,
+            WHITE,
+        );
+    }
+}
+
+/// Shown in place of the main menu until the first cluster snapshot arrives
+pub fn draw_loading_screen() {
+    let text = "Connecting to cluster...";
+    let text_dimensions = measure_text(text, None, 40, 1.);
+    let center = vec2(screen_width() / 2., screen_height() / 2.);
+    draw_text(
+        text,
+        center.x - text_dimensions.width / 2.,
+        center.y,
+        40.,
+        WHITE,
+    );
+
+    // dots chasing each other around a circle below the text
+    let dots = 8;
+    let head = (get_time() * 8.) as usize % dots;
+    for i in 0..dots {
+        let angle = i as f32 / dots as f32 * std::f32::consts::TAU;
+        let pos = center + vec2(angle.cos(), angle.sin()) * 20. + vec2(0., 60.);
+        let age = (head + dots - i) % dots;
+        let alpha = 1. - age as f32 / dots as f32;
+        draw_circle(pos.x, pos.y, 4., Color::new(1., 1., 1., alpha));
+    }
+}
+
+/// Kubeconfig contexts listed under the main menu prompt
+pub fn draw_context_picker() {
+    let contexts = storage::get::<KubeContexts>();
+    if contexts.names.is_empty() {
+        return;
+    }
+
+    let font_size = 20;
+    let padding = 6.;
+    let line_height = measure_text("Placeholder", None, font_size, 1.).height + padding;
+    let mut y = screen_height() / 2. + 50.;
+    let hint = "[Up/Down] Pick context | [Enter] Switch";
+    let hint_width = measure_text(hint, None, font_size, 1.).width;
+    draw_text(
+        hint,
+        screen_width() / 2. - hint_width / 2.,
+        y,
+        font_size as f32,
+        GRAY,
+    );
+    for (i, name) in contexts.names.iter().enumerate() {
+        y += line_height;
+        let marker = if i == contexts.selected { "> " } else { "  " };
+        let status = if contexts.switching.as_ref() == Some(name) {
+            " (switching...)"
+        } else if contexts.active.as_ref() == Some(name) {
+            " (active)"
+        } else {
+            ""
+        };
+        let text = format!("{marker}{name}{status}");
+        let text_width = measure_text(&text, None, font_size, 1.).width;
+        draw_text(
+            &text,
+            screen_width() / 2. - text_width / 2.,
+            y,
+            font_size as f32,
+            if i == contexts.selected {
+                YELLOW
+            } else {
+                WHITE
+            },
+        );
+    }
+    if let Some(err) = contexts.error.as_ref() {
+        let err_width = measure_text(err, None, font_size, 1.).width;
+        draw_text(
+            err,
+            screen_width() / 2. - err_width / 2.,
+            y + line_height * 1.5,
+            font_size as f32,
+            RED,
+        );
+    }
+}
+
+/// Frame rate, cluster size and message backlog in the bottom left corner,
+/// for performance reports
+pub fn draw_debug_overlay(backlog: usize) {
+    let game_state = storage::get::<GameState>();
+    if !game_state.debug_overlay {
+        return;
+    }
+    let game_resources = storage::get::<GameResources>();
+    let since_update = match game_state.last_update_at {
+        Some(at) => format!("{:.1}s", get_time() - at),
+        None => "never".to_string(),
+    };
+    let lines = [
+        format!("FPS: {}", get_fps()),
+        format!(
+            "Pods: {} | Nodes: {}",
+            game_resources.pods.len(),
+            game_resources.nodes.len()
+        ),
+        format!("Queued messages: {backlog}"),
+        format!("Last update: {since_update} ago"),
+    ];
+    let font_size = 18.;
+    let line_height = 20.;
+    let padding = 6.;
+    let width = 220.;
+    let height = line_height * lines.len() as f32 + padding * 2.;
+    let x = 10.;
+    let y = screen_height() - 40. - height;
+    draw_rectangle(x, y, width, height, Color::new(0., 0., 0., 0.7));
+    for (i, line) in lines.iter().enumerate() {
+        draw_text(
+            line,
+            x + padding,
+            y + padding + line_height * (i as f32 + 0.75),
+            font_size,
+            GREEN,
+        );
+    }
+}
+
+/// Transient banner near the top for [`GameState::alert`]
+pub fn draw_alert() {
+    let game_state = storage::get::<GameState>();
+    let Some((message, raised_at)) = game_state.alert.as_ref() else {
+        return;
+    };
+    if get_time() - raised_at > ALERT_SECONDS {
+        return;
+    }
+    let font_size = 22;
+    let padding = 8.;
+    let dim = measure_text(message, None, font_size, 1.);
+    let x = screen_width() / 2. - dim.width / 2.;
+    let y = 80.;
+    draw_rectangle(
+        x - padding,
+        y - dim.height - padding,
+        dim.width + padding * 2.,
+        dim.height + padding * 2.,
+        Color::new(0.6, 0.1, 0.1, 0.9),
+    );
+    draw_text(message, x, y, font_size as f32, WHITE);
+}
+
+const ACTION_ERROR_FONT_SIZE: u16 = 20;
+const ACTION_ERROR_PADDING: f32 = 10.;
+
+/// Screen rectangle of the toast for [`GameState::action_error`], clicking it
+/// dismisses the toast
+pub fn action_error_rect(reason: &str) -> Rect {
+    let text = action_error_text(reason);
+    let dim = measure_text(&text, None, ACTION_ERROR_FONT_SIZE, 1.);
+    let width = (dim.width + ACTION_ERROR_PADDING * 2.).min(screen_width() - 20.);
+    let height = dim.height + ACTION_ERROR_PADDING * 2.;
+    // above the navbar, on the right
+    Rect::new(
+        screen_width() - width - 10.,
+        screen_height() - height - 50.,
+        width,
+        height,
+    )
+}
+
+fn action_error_text(reason: &str) -> String {
+    format!("{reason} | [X] Dismiss")
+}
+
+/// Red toast with the reason of the last failed cluster action
+pub fn draw_action_error() {
+    let game_state = storage::get::<GameState>();
+    let Some(reason) = game_state.action_error.as_ref() else {
+        return;
+    };
+    let rect = action_error_rect(reason);
+    draw_rectangle(
+        rect.x,
+        rect.y,
+        rect.w,
+        rect.h,
+        Color::new(0.6, 0.1, 0.1, 0.95),
+    );
+    draw_rectangle_lines(rect.x, rect.y, rect.w, rect.h, 2., RED);
+    draw_text(
+        &truncate_text(
+            &action_error_text(reason),
+            rect.w - ACTION_ERROR_PADDING * 2.,
+            ACTION_ERROR_FONT_SIZE,
+        ),
+        rect.x + ACTION_ERROR_PADDING,
+        rect.y + rect.h - ACTION_ERROR_PADDING,
+        ACTION_ERROR_FONT_SIZE as f32,
+        WHITE,
+    );
+}
+
+/// Every unit as a table, an alternative to the node view
+pub fn draw_unit_list() {
+    let game_state = storage::get::<GameState>();
+    let rows = UnitRow::collect(
+        &storage::get::<GameResources>(),
+        game_state.list_sort,
+        game_state.list_sort_descending,
+    );
+
+    let font_size = 18;
+    let row_height = 22.;
+    let x = 10.;
+    let width = screen_width() - 20.;
+    let top = 130.;
+    let bottom = screen_height() - 50.;
+
+    let mut column_x = x;
+    for (i, column) in ListColumn::ALL.into_iter().enumerate() {
+        let mut title = format!("[{}] {}", i + 1, column.title());
+        if column == game_state.list_sort {
+            title.push_str(if game_state.list_sort_descending {
+                " v"
+            } else {
+                " ^"
+            });
+        }
+        let column_width = width * column.width();
+        draw_text(
+            &truncate_text(&title, column_width - 8., font_size),
+            column_x,
+            top,
+            font_size as f32,
+            GRAY,
+        );
+        column_x += column_width;
+    }
+
+    if rows.is_empty() {
+        draw_text("No units yet", x, top + row_height, font_size as f32, WHITE);
+        return;
+    }
+
+    // keep the selected row in view
+    let visible = (((bottom - top) / row_height) as usize).max(2) - 1;
+    let offset = (game_state.list_selected + 1).saturating_sub(visible);
+    for (i, row) in rows.iter().enumerate().skip(offset).take(visible) {
+        let y = top + row_height * (i - offset + 1) as f32;
+        if i == game_state.list_selected {
+            draw_rectangle(
+                x - 4.,
+                y - row_height + 6.,
+                width + 8.,
+                row_height,
+                Color::new(0.2, 0.2, 0.5, 0.8),
+            );
+        }
+        let mut column_x = x;
+        for column in ListColumn::ALL {
+            let column_width = width * column.width();
+            draw_text(
+                &truncate_text(&row.cell(column), column_width - 8., font_size),
+                column_x,
+                y,
+                font_size as f32,
+                WHITE,
+            );
+            column_x += column_width;
+        }
+    }
+}
+
+/// Creates in flight with a spinner, in the bottom right corner above the
+/// navbar
+pub fn draw_build_queue() {
+    let build_queue = storage::get::<BuildQueue>();
+    if build_queue.units.is_empty() {
+        return;
+    }
+
+    let font_size = 18;
+    let padding = 6.;
+    let line_height = measure_text("Placeholder", None, font_size, 1.).height + padding;
+    let width = 260.;
+    let height = line_height * (build_queue.units.len() + 1) as f32 + padding;
+    let x = screen_width() - width - 10.;
+    let y = screen_height() - height - 40.;
+    let spinner = ['|', '/', '-', '\\'][(get_time() * 8.) as usize % 4];
+
+    draw_rectangle(x, y, width, height, Color::new(0.1, 0.1, 0.1, 0.9));
+    draw_text(
+        &format!("Building {}", build_queue.units.len()),
+        x + padding,
+        y + line_height,
+        font_size as f32,
+        GRAY,
+    );
+    for (i, unit) in build_queue.units.iter().enumerate() {
+        let line = truncate_text(
+            &format!("{spinner} {} {}", unit.unit_type, unit.name),
+            width - padding * 2.,
+            font_size,
+        );
+        draw_text(
+            &line,
+            x + padding,
+            y + line_height * (i + 2) as f32,
+            font_size as f32,
+            WHITE,
+        );
+    }
+}
+
+/// Floating key labels next to what they act on, toggled with [K]
+pub fn draw_key_hints() {
+    let game_state = storage::get::<GameState>();
+    if !game_state.key_hints || game_state.palette.is_some() {
+        return;
+    }
+    let keymap = storage::get::<KeyMap>();
+    let key = |action| keymap.key_name(action);
+    let nav_keys = format!("[{} {}]", key(Action::NavLeft), key(Action::NavRight));
+    let hit_rects = storage::get::<HitRects>();
+    let find = |rects: &[(usize, Rect)], index| {
+        rects
+            .iter()
+            .find(|(i, _)| *i == index)
+            .map(|(_, rect)| *rect)
+    };
+
+    // labels by the rect they are drawn at and the side they go to
+    let mut hints = Vec::new();
+    match game_state.navigation_mode {
+        NavigationMode::Cluster => {
+            if let Some(node) = find(&hit_rects.nodes, game_state.selected_node_index) {
+                hints.push((
+                    format!("[{}] select node", key(Action::Select)),
+                    node,
+                    vec2(0., -1.),
+                ));
+                hints.push((format!("{nav_keys} switch node"), node, vec2(0., 1.)));
+                hints.push((
+                    format!("[{}] create", key(Action::CreateUnit)),
+                    node,
+                    vec2(1., 0.),
+                ));
+            }
+        }
+        NavigationMode::Node => {
+            if let Some((_, plane)) = hit_rects.nodes.first() {
+                hints.push((
+                    format!("[{}] back", key(Action::Back)),
+                    *plane,
+                    vec2(-1., 0.),
+                ));
+            }
+            if let Some(unit) = find(&hit_rects.units, game_state.selected_unit_index) {
+                hints.push((
+                    format!("[{}] delete", key(Action::Delete)),
+                    unit,
+                    vec2(0., -1.),
+                ));
+                hints.push((format!("{nav_keys} select unit"), unit, vec2(0., 1.)));
+            }
+        }
+        NavigationMode::Create | NavigationMode::Blueprint(_) | NavigationMode::List => return,
+    }
+
+    let font_size = 18;
+    let padding = 4.;
+    let gap = 6.;
+    for (hint, rect, side) in &hints {
+        let dim = measure_text(hint, None, font_size, 1.);
+        let size = vec2(dim.width + padding * 2., dim.height + padding * 2.);
+        // just past the edge of `rect` on `side`, kept on screen
+        let center = rect.center() + *side * (rect.size() / 2. + size / 2. + gap);
+        let x = (center.x - size.x / 2.).clamp(0., (screen_width() - size.x).max(0.));
+        let y = (center.y - size.y / 2.).clamp(0., (screen_height() - size.y).max(0.));
+        draw_rectangle(x, y, size.x, size.y, Color::new(0., 0., 0., 0.7));
+        draw_rectangle_lines(x, y, size.x, size.y, 1., YELLOW);
+        draw_text(
+            hint,
+            x + padding,
+            y + padding + dim.offset_y,
+            font_size as f32,
+            YELLOW,
+        );
+    }
+}
+
+/// Draw a rectangle, extruded by `depth` towards the top right in the
+/// isometric render style
+fn draw_box(x: f32, y: f32, w: f32, h: f32, depth: f32, color: Color) {
+    draw_rectangle(x, y, w, h, color);
+    if storage::get::<Settings>().render_style != RenderStyle::Isometric {
+        return;
+    }
+
+    let shade = |factor: f32| {
+        Color::new(
+            color.r * factor,
+            color.g * factor,
+            color.b * factor,
+            color.a,
+        )
+    };
+    let offset = vec2(depth, -depth);
+    // top face
+    draw_quad(
+        vec2(x, y),
+        vec2(x + w, y),
+        vec2(x + w, y) + offset,
+        vec2(x, y) + offset,
+        shade(0.85),
+    );
+    // side face
+    draw_quad(
+        vec2(x + w, y),
+        vec2(x + w, y + h),
+        vec2(x + w, y + h) + offset,
+        vec2(x + w, y) + offset,
+        shade(0.6),
+    );
+}
+
+fn draw_quad(a: Vec2, b: Vec2, c: Vec2, d: Vec2, color: Color) {
+    draw_triangle(a, b, c, color);
+    draw_triangle(a, c, d, color);
+}
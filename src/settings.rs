@@ -0,0 +1,271 @@
+//! Player preferences stored in `settings.json`
+
+use crate::economy::EconomyScaling;
+use crate::k8s::{CubeHarvestError, SESSION_ID, get_unit_type};
+use crate::keymap::Action;
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::ApiResource;
+use kube::api::DynamicObject;
+use kube::api::GroupVersionKind;
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+pub const SETTINGS_PATH: &str = "settings.json";
+/// Version of the [`Settings`] format written by this build, bump it and add a
+/// step to `Settings::migrate` when renaming or reshaping fields
+const SETTINGS_VERSION: u32 = 1;
+
+/// Player preferences, loaded from [`SETTINGS_PATH`] at startup and saved
+/// back when changed in game.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Format version of the file, files without one are version 0
+    pub version: u32,
+    /// Pause the game when the cluster becomes unreachable
+    pub auto_pause_on_error: bool,
+    /// Resume the game once the cluster is reachable again, only applies to
+    /// pauses caused by `auto_pause_on_error`
+    pub auto_resume: bool,
+    /// Stop polling the cluster and show a sleep screen after this many
+    /// seconds without input, e.g. for unattended booths
+    pub idle_sleep_seconds: Option<u64>,
+    /// Warn when a created unit hasn't shown up after this many seconds
+    pub creation_timeout_seconds: Option<u64>,
+    /// Seconds a credit change stays highlighted next to the credits display
+    pub credit_delta_seconds: f64,
+    /// Pulse the credits display once credits reach this amount
+    pub credits_high_threshold: Option<usize>,
+    /// Warn when credits drop below this amount
+    pub credits_low_threshold: Option<usize>,
+    /// Nodes other than the first one have to be unlocked with credits before
+    /// units can be built on them, and units are pinned to the selected node
+    pub node_budget: bool,
+    /// Credits needed to unlock a node with `node_budget`
+    pub node_unlock_price: usize,
+    pub render_style: RenderStyle,
+    /// Outline the node plane and draw the node name on it
+    pub node_labels: bool,
+    pub unit_label: UnitLabel,
+    pub miner_target: MinerTarget,
+    pub economy_scaling: EconomyScaling,
+    /// Run the economy ticks this many times faster, for presenting the game
+    pub demo_speed: f32,
+    /// Extra unit type backed by a custom resource, off when `None`
+    pub advanced_unit: Option<AdvancedUnit>,
+    /// What new units are created as
+    pub unit_workload: UnitWorkload,
+    /// Most game units the namespace may hold, creating more is refused.
+    /// Unlimited when `None`
+    pub max_units: Option<usize>,
+    /// Also create a ResourceQuota capping the namespace at `max_units` pods
+    /// at startup, so the apiserver enforces it too
+    pub enforce_quota: bool,
+    /// Create processors as a StatefulSet behind a headless Service instead,
+    /// so miners target a DNS name that stays the same across restarts
+    pub stateful_processors: bool,
+    /// Keys bound to game actions by name, e.g. `"create_unit": "N"`, unbound
+    /// actions keep their default key
+    pub keymap: HashMap<Action, String>,
+    /// Seconds a navigation key is held before it repeats
+    pub key_repeat_delay_seconds: f64,
+    /// Seconds between repeats of a held navigation key
+    pub key_repeat_interval_seconds: f64,
+    /// Player name from `--owner`, put on created units, not saved
+    #[serde(skip)]
+    pub owner: Option<String>,
+}
+
+/// Unit created as a custom resource for an operator to reconcile, the pods it
+/// owns show up as advanced units
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdvancedUnit {
+    group: String,
+    pub version: String,
+    pub kind: String,
+    /// Resource name in the API path, guessed from `kind` when `None`
+    #[serde(default)]
+    plural: Option<String>,
+    /// JSON manifest of the custom resource, its name and labels are set by
+    /// the game
+    pub template: PathBuf,
+    #[serde(default = "AdvancedUnit::default_price")]
+    pub price: usize,
+}
+
+impl AdvancedUnit {
+    fn default_price() -> usize {
+        10
+    }
+
+    pub fn api_resource(&self) -> ApiResource {
+        let gvk = GroupVersionKind::gvk(&self.group, &self.version, &self.kind);
+        match &self.plural {
+            Some(plural) => ApiResource::from_gvk_with_plural(&gvk, plural),
+            None => ApiResource::from_gvk(&gvk),
+        }
+    }
+
+    pub fn build(&self, name: String) -> Result<DynamicObject, CubeHarvestError> {
+        let content = std::fs::read_to_string(&self.template)?;
+        let mut object = serde_json::from_str::<DynamicObject>(&content)?;
+        object.metadata.name = Some(name);
+        let labels = object.metadata.labels.get_or_insert_default();
+        labels.insert(
+            "cube-harvest.io/unit-type".to_string(),
+            "advanced".to_string(),
+        );
+        labels.insert("cube-harvest.io/session-id".to_string(), SESSION_ID.clone());
+        Ok(object)
+    }
+
+    /// Whether the pod belongs to a custom resource of this kind, either
+    /// directly owned or labeled by an operator that copies our labels
+    pub fn owns(&self, pod: &Pod) -> bool {
+        get_unit_type(pod).as_deref() == Some("advanced")
+            || pod
+                .metadata
+                .owner_references
+                .iter()
+                .flatten()
+                .any(|owner| owner.kind == self.kind)
+    }
+}
+
+/// Kind of object created for a new unit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnitWorkload {
+    #[default]
+    Pod,
+    /// Single replica Deployment, so the unit is rescheduled when its node
+    /// goes away
+    Deployment,
+}
+
+/// How new miners refer to their processor
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MinerTarget {
+    #[default]
+    Ip,
+    /// The processor pod name, which survives the processor getting a new IP
+    Name,
+}
+
+/// Text drawn on unit sprites
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnitLabel {
+    #[default]
+    Ip,
+    Name,
+    /// Time since the pod was created
+    Age,
+    None,
+}
+
+/// How the node plane and units are drawn, both styles share the same layout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderStyle {
+    #[default]
+    Flat,
+    /// Boxes with a top and side face for a sense of depth
+    Isometric,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            auto_pause_on_error: false,
+            auto_resume: false,
+            idle_sleep_seconds: None,
+            creation_timeout_seconds: Some(30),
+            credit_delta_seconds: 1.5,
+            credits_high_threshold: None,
+            credits_low_threshold: None,
+            node_budget: false,
+            node_unlock_price: 50,
+            render_style: RenderStyle::default(),
+            node_labels: true,
+            unit_label: UnitLabel::default(),
+            miner_target: MinerTarget::default(),
+            economy_scaling: EconomyScaling::default(),
+            demo_speed: 1.,
+            advanced_unit: None,
+            unit_workload: UnitWorkload::default(),
+            stateful_processors: false,
+            max_units: Some(100),
+            enforce_quota: false,
+            keymap: HashMap::new(),
+            key_repeat_delay_seconds: 0.4,
+            key_repeat_interval_seconds: 0.08,
+            owner: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Load settings from disk, falling back to defaults if the file is
+    /// missing or malformed. Older files are migrated and written back, missing
+    /// fields get their defaults and unknown ones are ignored.
+    pub fn load() -> Self {
+        let Ok(content) = std::fs::read_to_string(SETTINGS_PATH) else {
+            return Self::default();
+        };
+        let mut value = match serde_json::from_str::<serde_json::Value>(&content) {
+            Ok(value) => value,
+            Err(err) => {
+                println!("ignore malformed {SETTINGS_PATH}: {err}");
+                return Self::default();
+            }
+        };
+        let version = value.get("version").and_then(|v| v.as_u64()).unwrap_or(0) as u32;
+        if version < SETTINGS_VERSION {
+            Self::migrate(&mut value, version);
+        }
+        let settings = match serde_json::from_value::<Self>(value) {
+            Ok(settings) => settings,
+            Err(err) => {
+                println!("ignore malformed {SETTINGS_PATH}: {err}");
+                return Self::default();
+            }
+        };
+        // only write back migrated files, one from a newer build is kept as is
+        if version < SETTINGS_VERSION
+            && let Err(err) = settings.save()
+        {
+            println!("failed to save migrated {SETTINGS_PATH}: {err}");
+        }
+        settings
+    }
+
+    /// Upgrade the raw settings of an older format to [`SETTINGS_VERSION`]
+    fn migrate(value: &mut serde_json::Value, mut version: u32) {
+        while version < SETTINGS_VERSION {
+            match version {
+                // unversioned files have the same fields as version 1
+                0 => {}
+                _ => unreachable!("no migration from settings version {version}"),
+            }
+            version += 1;
+        }
+        if let Some(object) = value.as_object_mut() {
+            object.insert("version".to_string(), version.into());
+        }
+    }
+
+    /// `demo_speed` within sane bounds
+    pub fn demo_speed(&self) -> f32 {
+        self.demo_speed.clamp(0.25, 5.)
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        std::fs::write(SETTINGS_PATH, content)
+    }
+}
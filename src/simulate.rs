@@ -0,0 +1,290 @@
+//! In-memory cluster for `--simulate`, to play without a kube apiserver
+
+use crate::economy::GameConfig;
+use crate::k8s::{
+    ClusterBackend, CubeHarvestError, GameMessage, GameResources, TARGET_ANNOTATION, build_unit,
+    get_owner, get_unit_name, node_load,
+};
+use crate::state::CreateTarget;
+use k8s_openapi::api::core::v1::{Node, NodeStatus, Pod, PodCondition, PodStatus};
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::OwnerReference;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use k8s_openapi::chrono::Utc;
+use kube::api::ObjectMeta;
+use kube::core::ErrorResponse;
+use std::collections::BTreeMap;
+use tokio::sync::mpsc::Sender;
+
+const NODE_COUNT: usize = 3;
+
+/// Nodes and pods kept in memory, pods are scheduled and running as soon as
+/// they are created if a node has room for them
+pub struct SimulatedCluster {
+    pods: BTreeMap<String, Pod>,
+    nodes: Vec<Node>,
+    game_config: GameConfig,
+    /// Only the pods of this owner are in snapshots, see
+    /// [`GameMessage::FilterOwner`]
+    owner_filter: Option<String>,
+    /// Pod IPs are handed out in order
+    next_ip: u32,
+}
+
+impl SimulatedCluster {
+    /// A few nodes, with a processor and two miners of `owner` to start
+    /// earning right away
+    pub fn new(game_config: GameConfig, owner: Option<String>) -> Self {
+        let nodes = (1..=NODE_COUNT)
+            .map(|i| Node {
+                metadata: ObjectMeta {
+                    name: Some(format!("sim-node-{i}")),
+                    ..Default::default()
+                },
+                status: Some(NodeStatus {
+                    allocatable: Some(BTreeMap::from([
+                        ("cpu".to_string(), Quantity("4".to_string())),
+                        ("memory".to_string(), Quantity("8Gi".to_string())),
+                    ])),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .collect();
+        let mut cluster = Self {
+            pods: BTreeMap::new(),
+            nodes,
+            game_config,
+            owner_filter: owner.clone(),
+            next_ip: 1,
+        };
+
+        let processor = build_unit(
+            "processor-sim".to_string(),
+            &CreateTarget::Processor,
+            String::new(),
+            owner.as_deref(),
+            &cluster.game_config,
+        )
+        .expect("failed to build simulated processor");
+        cluster
+            .create(processor)
+            .expect("failed to create simulated processor");
+        let target = cluster.pods["processor-sim"]
+            .status
+            .as_ref()
+            .and_then(|s| s.pod_ip.clone())
+            .unwrap_or_default();
+        for i in 1..=2 {
+            let miner = build_unit(
+                format!("miner-sim-{i}"),
+                &CreateTarget::Miner,
+                target.clone(),
+                owner.as_deref(),
+                &cluster.game_config,
+            )
+            .expect("failed to build simulated miner");
+            cluster
+                .create(miner)
+                .expect("failed to create simulated miner");
+        }
+        cluster
+    }
+
+    pub fn snapshot(&self) -> GameResources {
+        GameResources::from_items(
+            self.pods
+                .values()
+                .filter(|p| {
+                    self.owner_filter
+                        .as_deref()
+                        .is_none_or(|owner| get_owner(p) == Some(owner))
+                })
+                .cloned()
+                .collect(),
+            Some(self.nodes.clone()),
+            self.game_config.processor_capacity,
+        )
+    }
+
+    /// Least loaded node with room for the requests of `pod`
+    fn schedule(&self, pod: &Pod) -> Option<String> {
+        self.nodes
+            .iter()
+            .filter_map(|node| {
+                let node_name = node.metadata.name.as_ref()?;
+                let mut pods = self
+                    .pods
+                    .values()
+                    .filter(|p| {
+                        p.spec.as_ref().and_then(|s| s.node_name.as_ref()) == Some(node_name)
+                    })
+                    .collect::<Vec<_>>();
+                pods.push(pod);
+                let load = node_load(node, &pods).unwrap_or(0.);
+                (load <= 1.).then(|| (node_name.clone(), load))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(node_name, _)| node_name)
+    }
+
+    /// Schedule `pod` unless it picked a node and start it, it stays pending
+    /// when no node has room left
+    fn create(&mut self, mut pod: Pod) -> Result<(), CubeHarvestError> {
+        let name = pod.metadata.name.clone().unwrap_or_default();
+        if self.pods.contains_key(&name) {
+            return Err(api_error(
+                409,
+                "AlreadyExists",
+                format!("pods \"{name}\" already exists"),
+            ));
+        }
+
+        if pod.spec.as_ref().is_none_or(|s| s.node_name.is_none()) {
+            let node_name = self.schedule(&pod);
+            pod.spec.get_or_insert_default().node_name = node_name;
+        }
+        let now = Time(Utc::now());
+        pod.metadata.creation_timestamp = Some(now.clone());
+        pod.status = Some(
+            if pod.spec.as_ref().is_some_and(|s| s.node_name.is_some()) {
+                PodStatus {
+                    phase: Some("Running".to_string()),
+                    pod_ip: Some(format!(
+                        "10.244.{}.{}",
+                        self.next_ip / 256,
+                        self.next_ip % 256
+                    )),
+                    start_time: Some(now),
+                    ..Default::default()
+                }
+            } else {
+                PodStatus {
+                    phase: Some("Pending".to_string()),
+                    conditions: Some(vec![PodCondition {
+                        type_: "PodScheduled".to_string(),
+                        status: "False".to_string(),
+                        reason: Some("Unschedulable".to_string()),
+                        ..Default::default()
+                    }]),
+                    ..Default::default()
+                }
+            },
+        );
+        self.next_ip += 1;
+        self.pods.insert(name, pod);
+        Ok(())
+    }
+}
+
+fn api_error(code: u16, reason: &str, message: String) -> CubeHarvestError {
+    CubeHarvestError::Kube(kube::Error::Api(ErrorResponse {
+        status: "Failure".to_string(),
+        message,
+        reason: reason.to_string(),
+        code,
+    }))
+}
+
+fn not_found(name: &str) -> CubeHarvestError {
+    api_error(404, "NotFound", format!("pods \"{name}\" not found"))
+}
+
+impl ClusterBackend for SimulatedCluster {
+    async fn apply(
+        &mut self,
+        msg: GameMessage,
+        tx: &Sender<GameMessage>,
+    ) -> Result<(), CubeHarvestError> {
+        match msg {
+            GameMessage::CreatePod(pod) => self.create(*pod)?,
+            GameMessage::CreateDeployment(deployment) => {
+                let name = deployment.metadata.name.clone().unwrap_or_default();
+                let template = deployment
+                    .spec
+                    .map(|spec| spec.template)
+                    .unwrap_or_default();
+                let mut metadata = template.metadata.unwrap_or_default();
+                metadata.name = Some(format!("{name}-{}", self.next_ip));
+                self.create(Pod {
+                    metadata,
+                    spec: template.spec,
+                    ..Default::default()
+                })?;
+            }
+            // the pod gets the name, hostname and subdomain the StatefulSet
+            // controller would give it
+            GameMessage::CreateStatefulSet(stateful_set, _) => {
+                let name = stateful_set.metadata.name.clone().unwrap_or_default();
+                let spec = stateful_set.spec.unwrap_or_default();
+                let mut metadata = spec.template.metadata.unwrap_or_default();
+                metadata.name = Some(format!("{name}-0"));
+                metadata.owner_references = Some(vec![OwnerReference {
+                    api_version: "apps/v1".to_string(),
+                    kind: "StatefulSet".to_string(),
+                    name: name.clone(),
+                    controller: Some(true),
+                    ..Default::default()
+                }]);
+                let mut pod_spec = spec.template.spec.unwrap_or_default();
+                pod_spec.hostname = Some(format!("{name}-0"));
+                pod_spec.subdomain = spec.service_name;
+                self.create(Pod {
+                    metadata,
+                    spec: Some(pod_spec),
+                    ..Default::default()
+                })?;
+            }
+            GameMessage::DeleteDeployment(name) | GameMessage::DeleteStatefulSet(name) => {
+                self.pods
+                    .retain(|_, p| get_unit_name(p) != Some(name.as_str()));
+            }
+            GameMessage::DeletePod(name, _) => {
+                self.pods.remove(&name).ok_or_else(|| not_found(&name))?;
+            }
+            GameMessage::ConvertUnit(name, target, miner_target) => {
+                let pod = self.pods.get_mut(&name).ok_or_else(|| not_found(&name))?;
+                pod.metadata.labels.get_or_insert_default().insert(
+                    "cube-harvest.io/unit-type".to_string(),
+                    target.unit_type().to_string(),
+                );
+                pod.metadata
+                    .annotations
+                    .get_or_insert_default()
+                    .insert(TARGET_ANNOTATION.to_string(), miner_target);
+            }
+            GameMessage::CreateAdvancedUnit(object, _) => {
+                tx.send(GameMessage::ActionFailed(format!(
+                    "{} can't be simulated",
+                    object.types.map(|t| t.kind).unwrap_or_default()
+                )))
+                .await?;
+            }
+            GameMessage::FetchLogs(name) => {
+                tx.send(GameMessage::Logs(format!(
+                    "{name} is simulated, it has no logs"
+                )))
+                .await?;
+            }
+            // there are no contexts to pick from
+            GameMessage::SwitchContext(name) => {
+                tx.send(GameMessage::ActionFailed(format!(
+                    "can't switch to {name} while simulating"
+                )))
+                .await?;
+            }
+            GameMessage::FilterOwner(owner) => self.owner_filter = owner,
+            GameMessage::UpdateResources(_)
+            | GameMessage::CreateFailed(_)
+            | GameMessage::ActionFailed(_)
+            | GameMessage::ConnectionLost(_)
+            | GameMessage::ConnectionRestored
+            | GameMessage::Sleep
+            | GameMessage::Wake
+            | GameMessage::Logs(_)
+            | GameMessage::ContextSwitched(_)
+            | GameMessage::ContextSwitchFailed(..) => return Err(CubeHarvestError::NotAnAction),
+        }
+        Ok(())
+    }
+}
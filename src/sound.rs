@@ -0,0 +1,66 @@
+//! Sound cues of game events, silent unless built with the `sound` feature
+
+#[cfg(feature = "sound")]
+use macroquad::audio::{Sound, load_sound, play_sound_once};
+#[cfg(feature = "sound")]
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cue {
+    /// A unit was sent to the cluster
+    Create,
+    /// A unit deletion was confirmed
+    Delete,
+    /// Not enough credits for the unit
+    Denied,
+}
+
+#[cfg(feature = "sound")]
+impl Cue {
+    const ALL: [Self; 3] = [Self::Create, Self::Delete, Self::Denied];
+
+    fn file_name(self) -> &'static str {
+        match self {
+            Self::Create => "blip.wav",
+            Self::Delete => "thunk.wav",
+            Self::Denied => "buzz.wav",
+        }
+    }
+}
+
+/// Sounds loaded once from the assets folder, missing files stay silent
+#[derive(Default)]
+pub struct Sounds {
+    #[cfg(feature = "sound")]
+    sounds: HashMap<Cue, Sound>,
+}
+
+impl Sounds {
+    #[cfg(feature = "sound")]
+    pub async fn load() -> Self {
+        let mut sounds = HashMap::new();
+        for cue in Cue::ALL {
+            match load_sound(cue.file_name()).await {
+                Ok(sound) => {
+                    sounds.insert(cue, sound);
+                }
+                Err(err) => println!("sound {} not loaded: {err}", cue.file_name()),
+            }
+        }
+        Self { sounds }
+    }
+
+    #[cfg(not(feature = "sound"))]
+    pub async fn load() -> Self {
+        Self::default()
+    }
+
+    pub fn play(&self, cue: Cue) {
+        #[cfg(feature = "sound")]
+        if let Some(sound) = self.sounds.get(&cue) {
+            play_sound_once(sound);
+        }
+        #[cfg(not(feature = "sound"))]
+        let _ = cue;
+    }
+}
@@ -0,0 +1,1238 @@
+//! Game state shared through macroquad storage, and the commands changing it
+
+use crate::economy::GameConfig;
+use crate::k8s::{
+    CubeHarvestError, GameMessage, GameResources, SESSION_ID, build_unit, delete_unit_message,
+    deployment_for, get_env_target, get_session_id, get_unit_ip, get_unit_name, get_unit_target,
+    get_unit_type, is_image_pull_failing, is_stuck_terminating, is_unschedulable, is_workload_pod,
+    kube_contexts, processor_targets, stateful_set_for, target_key, unit_age,
+};
+use crate::render::{format_age, units_per_page};
+use crate::settings::{MinerTarget, SETTINGS_PATH, Settings, UnitLabel, UnitWorkload};
+use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::chrono::Utc;
+use macroquad::experimental::collections::storage;
+use macroquad::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
+
+const BLUEPRINTS_DIR: &str = "blueprints";
+/// Progress of the last session, resumed on launch
+const SAVE_PATH: &str = "save.json";
+/// Where older versions kept the credits and the unlocked nodes, read when
+/// there is no [`SAVE_PATH`] yet
+const LEGACY_CREDITS_PATH: &str = "credits.dat";
+const LEGACY_UNLOCKED_NODES_PATH: &str = "unlocked-nodes.json";
+/// Most units built at once in Create mode
+pub const MAX_CREATE_COUNT: usize = 20;
+
+pub enum GameStage {
+    MainMenu,
+    Playing,
+    Paused,
+    GameOver,
+}
+
+#[derive(Debug, Clone)]
+pub enum NavigationMode {
+    Cluster,
+    Node,
+    Create,
+    /// Typing the name of a blueprint to save or load
+    Blueprint(BlueprintAction),
+    /// Table of every unit instead of the node view
+    List,
+}
+
+/// Columns of the unit list, sorted with the number keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListColumn {
+    Name,
+    Type,
+    Node,
+    Phase,
+    Ip,
+    Target,
+    Age,
+    Earnings,
+}
+
+impl ListColumn {
+    pub const ALL: [Self; 8] = [
+        Self::Name,
+        Self::Type,
+        Self::Node,
+        Self::Phase,
+        Self::Ip,
+        Self::Target,
+        Self::Age,
+        Self::Earnings,
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::Name => "Name",
+            Self::Type => "Type",
+            Self::Node => "Node",
+            Self::Phase => "Phase",
+            Self::Ip => "IP",
+            Self::Target => "Target",
+            Self::Age => "Age",
+            Self::Earnings => "Credits/s",
+        }
+    }
+
+    /// Share of the list width
+    pub fn width(self) -> f32 {
+        match self {
+            Self::Name => 0.2,
+            Self::Type => 0.1,
+            Self::Node => 0.15,
+            Self::Phase => 0.1,
+            Self::Ip => 0.13,
+            Self::Target => 0.14,
+            Self::Age => 0.07,
+            Self::Earnings => 0.11,
+        }
+    }
+}
+
+/// A game unit as shown in the unit list
+pub struct UnitRow {
+    pub name: String,
+    pub unit_type: String,
+    pub node: String,
+    pub phase: String,
+    pub ip: String,
+    pub target: String,
+    pub age: Option<Duration>,
+    earnings: usize,
+}
+
+impl UnitRow {
+    /// Game units of the cluster sorted by `column`
+    pub fn collect(
+        game_resources: &GameResources,
+        column: ListColumn,
+        descending: bool,
+    ) -> Vec<Self> {
+        let earning_miners = game_resources.earning_miners();
+        let now = Utc::now();
+        let mut rows = game_resources
+            .pods
+            .iter()
+            .enumerate()
+            .filter_map(|(i, p)| {
+                let unit_type = get_unit_type(p)?;
+                let name = p.metadata.name.clone()?;
+                let earnings = match unit_type.as_str() {
+                    "processor" => earning_miners.values().filter(|&&j| j == i).count(),
+                    _ => usize::from(earning_miners.contains_key(name.as_str())),
+                };
+                Some(Self {
+                    node: p
+                        .spec
+                        .as_ref()
+                        .and_then(|s| s.node_name.clone())
+                        .unwrap_or_default(),
+                    phase: if is_unschedulable(p) {
+                        "Pending (node full)".to_string()
+                    } else if is_image_pull_failing(p) {
+                        "Pending (image pull failed)".to_string()
+                    } else {
+                        p.status
+                            .as_ref()
+                            .and_then(|s| s.phase.clone())
+                            .unwrap_or_default()
+                    },
+                    ip: get_unit_ip(p).unwrap_or_default().to_string(),
+                    target: match unit_type.as_str() {
+                        "miner" => get_unit_target(p).unwrap_or_default(),
+                        _ => String::new(),
+                    },
+                    age: unit_age(p, now),
+                    name,
+                    unit_type,
+                    earnings,
+                })
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| {
+            let ordering = match column {
+                ListColumn::Age => a.age.cmp(&b.age),
+                ListColumn::Earnings => a.earnings.cmp(&b.earnings),
+                _ => a.cell(column).cmp(&b.cell(column)),
+            };
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        rows
+    }
+
+    pub fn cell(&self, column: ListColumn) -> String {
+        match column {
+            ListColumn::Name => self.name.clone(),
+            ListColumn::Type => self.unit_type.clone(),
+            ListColumn::Node => self.node.clone(),
+            ListColumn::Phase => self.phase.clone(),
+            ListColumn::Ip => self.ip.clone(),
+            ListColumn::Target => self.target.clone(),
+            ListColumn::Age => self.age.map(format_age).unwrap_or_default(),
+            ListColumn::Earnings => format!("+{}", self.earnings),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum BlueprintAction {
+    Save,
+    Load,
+}
+
+#[derive(Debug, Clone)]
+pub enum CreateTarget {
+    Miner,
+    Processor,
+}
+
+impl CreateTarget {
+    fn from_unit_type(unit_type: &str) -> Option<Self> {
+        match unit_type {
+            "miner" => Some(Self::Miner),
+            "processor" => Some(Self::Processor),
+            _ => None,
+        }
+    }
+
+    pub fn unit_type(&self) -> &'static str {
+        match self {
+            Self::Miner => "miner",
+            Self::Processor => "processor",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GameState {
+    pub selected_node_index: usize,
+    pub navigation_mode: NavigationMode,
+    pub create_target: Option<CreateTarget>,
+    pub create_text_buf: String,
+    /// Name of the unit being created, a random one when left blank
+    pub create_name_buf: String,
+    /// Typing `create_name_buf`, before the target of a miner
+    pub create_naming: bool,
+    /// Units to build at once, typed before picking the unit type, one when 0
+    pub create_count: usize,
+    /// Index into [`GameResources::processors`] picked as the miner target
+    pub create_target_selection: Option<usize>,
+    pub credits: usize,
+    pub miner_price: usize,
+    pub processor_price: usize,
+    /// The economy coroutines skip their ticks while this is set
+    pub paused: bool,
+    /// Last error reported by the reconciliation loop, cleared on recovery
+    pub cluster_error: Option<String>,
+    /// Open command palette, captures all input while set
+    pub palette: Option<CommandPalette>,
+    /// Pod name and its logs shown in an overlay, the logs are `None` until
+    /// `GameMessage::Logs` arrives
+    pub logs: Option<(String, Option<String>)>,
+    /// Open node search of the cluster view, captures all input while set
+    pub node_search: Option<NodeSearch>,
+    /// Miners from a loaded blueprint, waiting for their processor to get an IP
+    pub pending_blueprint_miners: Vec<PendingMiner>,
+    /// Show the action keys next to the elements they act on
+    pub key_hints: bool,
+    /// Help overlay toggled with [H], the game keeps running behind it
+    pub help: bool,
+    /// Names of the nodes unlocked with `Settings::node_budget`
+    pub unlocked_nodes: HashSet<String>,
+    /// Unit waiting for the player to confirm converting it to another type,
+    /// with the target typed in for a miner
+    pub pending_conversion: Option<(String, CreateTarget, String)>,
+    /// Stuck pod waiting for the player to confirm force deleting it
+    pub pending_force_delete: Option<String>,
+    /// Pod waiting for the player to confirm deleting its unit
+    pub pending_delete: Option<String>,
+    /// Pods of the selected node waiting for the player to confirm deleting
+    /// all their units
+    pub pending_bulk_delete: Option<Vec<String>>,
+    /// Miner target matching no processor, waiting for the player to confirm
+    /// creating the miner anyway
+    pub pending_unknown_target: bool,
+    /// Close the window at the start of the next frame
+    pub quit_requested: bool,
+    /// Banner message and the `get_time()` it was raised at, shown for
+    /// [`crate::render::ALERT_SECONDS`]
+    pub alert: Option<(String, f64)>,
+    /// Reason of the last failed cluster action, shown until dismissed
+    pub action_error: Option<String>,
+    /// Row of the unit list under the cursor
+    pub list_selected: usize,
+    pub list_sort: ListColumn,
+    pub list_sort_descending: bool,
+    /// Recent credit changes from economy ticks and the `get_time()` they
+    /// happened at
+    pub credit_deltas: Vec<(i64, f64)>,
+    /// Multiplier of economy ticks from `Settings::economy_scaling`, unscaled
+    /// when `None`
+    pub economy_scale: Option<f64>,
+    /// Credits per second earned and consumed as of the last economy ticks
+    pub last_earn_rate: f64,
+    pub last_consume_rate: f64,
+    /// Showing the units of every owner instead of only those of `--owner`
+    pub show_all_owners: bool,
+    /// Draw the frame rate and cluster stats, toggled with [F3]
+    pub debug_overlay: bool,
+    /// `get_time` of the last `UpdateResources`
+    pub last_update_at: Option<f64>,
+    /// Seconds of play the credits couldn't cover the upkeep for
+    pub broke_for: f32,
+    /// Set by `consume_credits` once broke for
+    /// `GameConfig::game_over_grace_seconds`, the main loop ends the game
+    pub should_game_over: bool,
+    // view state local to the selected node, reset by `set_selected_node`
+    /// Index into [`GameResources::pods_on_node`] of the selected node
+    pub selected_unit_index: usize,
+    /// Index of the first unit drawn on the node plane, see [`units_per_page`]
+    pub unit_scroll_offset: usize,
+}
+
+impl GameState {
+    /// Select another node, always go through this so the state pointing
+    /// into the previous node doesn't leak onto the new one
+    pub fn set_selected_node(&mut self, index: usize) {
+        if index == self.selected_node_index {
+            return;
+        }
+        self.selected_node_index = index;
+        self.selected_unit_index = 0;
+        self.unit_scroll_offset = 0;
+    }
+
+    /// Whether the keyboard currently types into a text field
+    pub fn is_typing(&self) -> bool {
+        if self.node_search.is_some()
+            || self.palette.is_some()
+            || matches!(self.pending_conversion, Some((_, CreateTarget::Miner, _)))
+        {
+            return true;
+        }
+        match self.navigation_mode {
+            NavigationMode::Blueprint(_) => true,
+            NavigationMode::Create => {
+                self.create_naming || matches!(self.create_target, Some(CreateTarget::Miner))
+            }
+            NavigationMode::Cluster | NavigationMode::Node | NavigationMode::List => false,
+        }
+    }
+
+    /// Whether units can be built on the node, the first node is always free
+    pub fn is_node_unlocked(&self, index: usize, node_name: &str) -> bool {
+        index == 0 || self.unlocked_nodes.contains(node_name)
+    }
+
+    pub fn selected_node_name(&self) -> Option<String> {
+        storage::get::<GameResources>()
+            .nodes
+            .get(self.selected_node_index)?
+            .metadata
+            .name
+            .clone()
+    }
+
+    pub fn alert(&mut self, message: impl Into<String>) {
+        self.alert = Some((message.into(), get_time()));
+    }
+
+    /// Scroll the node plane just enough to show the selected unit
+    pub fn scroll_to_selected_unit(&mut self) {
+        let per_page = units_per_page();
+        let count = self.selected_node_unit_count();
+        if self.selected_unit_index < self.unit_scroll_offset {
+            self.unit_scroll_offset = self.selected_unit_index;
+        } else if self.selected_unit_index >= self.unit_scroll_offset + per_page {
+            self.unit_scroll_offset = self.selected_unit_index + 1 - per_page;
+        }
+        // don't leave empty room behind once units are gone
+        self.unit_scroll_offset = self.unit_scroll_offset.min(count.saturating_sub(per_page));
+    }
+
+    /// Number of pods on the selected node
+    pub fn selected_node_unit_count(&self) -> usize {
+        self.selected_node_name()
+            .map(|name| storage::get::<GameResources>().pods_on_node(&name).len())
+            .unwrap_or(0)
+    }
+
+    pub fn selected_unit(&self) -> Option<Pod> {
+        let node_name = self.selected_node_name()?;
+        storage::get::<GameResources>()
+            .pods_on_node(&node_name)
+            .get(self.selected_unit_index)
+            .map(|&p| p.clone())
+    }
+
+    /// Units the Create mode builds, see `create_count`
+    pub fn create_batch(&self) -> usize {
+        self.create_count.max(1)
+    }
+
+    /// Name typed in Create mode for the `index`th unit of the batch, a
+    /// batch of named units is numbered
+    pub fn create_unit_name(&self, index: usize) -> String {
+        if self.create_batch() > 1 {
+            format!("{}-{}", self.create_name_buf, index + 1)
+        } else {
+            self.create_name_buf.clone()
+        }
+    }
+
+    /// Combined price of the units the Create mode builds
+    pub fn create_price(&self, target: &CreateTarget) -> usize {
+        let price = match target {
+            CreateTarget::Miner => self.miner_price,
+            CreateTarget::Processor => self.processor_price,
+        };
+        price * self.create_batch()
+    }
+
+    /// Credits per second of `credits` every `interval` seconds, scaled like
+    /// [`Self::apply_credit_tick`]
+    pub fn rate_per_second(&self, credits: usize, interval: f32) -> f64 {
+        credits as f64 * self.economy_scale.unwrap_or(1.) / interval as f64
+    }
+
+    /// Apply an economy tick to the credits and remember the change so it
+    /// can be highlighted
+    pub fn apply_credit_tick(&mut self, earned: usize, consumed: usize) {
+        let scale = self.economy_scale.unwrap_or(1.);
+        let earned = (earned as f64 * scale).round() as usize;
+        let consumed = (consumed as f64 * scale).round() as usize;
+        let before = self.credits;
+        self.credits = self.credits.saturating_add(earned).saturating_sub(consumed);
+        let delta = self.credits as i64 - before as i64;
+        if delta != 0 {
+            self.credit_deltas.push((delta, get_time()));
+        }
+    }
+}
+
+/// Actions shared by the keybindings and the command palette
+#[derive(Debug, Clone, Copy)]
+pub enum Command {
+    NextNode,
+    PreviousNode,
+    EnterNode,
+    JumpToNode(usize),
+    BackToCluster,
+    CreateUnit,
+    /// Delete the selected unit of the selected node
+    DeleteUnit,
+    /// Delete every unit of the selected node
+    DeleteNodeUnits,
+    NextUnit,
+    PreviousUnit,
+    BuildMiner,
+    BuildProcessor,
+    /// Create the custom resource of `Settings::advanced_unit`
+    BuildAdvanced,
+    SaveBlueprint,
+    LoadBlueprint,
+    DeleteSessionUnits,
+    ToggleKeyHints,
+    CycleUnitLabel,
+    CycleDemoSpeed,
+    ToggleListView,
+    /// Switch between the units of every owner and only those of `--owner`
+    ToggleOwnerFilter,
+    UnlockNode,
+    /// Ask to convert the pod at this index of `GameResources::pods` to the
+    /// other unit type
+    ConvertUnit(usize),
+    /// Ask to force delete the pod at this index of `GameResources::pods`,
+    /// only offered for pods stuck terminating
+    ForceDelete(usize),
+    Quit,
+}
+
+pub fn run_command(game_state: &mut GameState, command: Command, k_tx: &Sender<GameMessage>) {
+    match command {
+        Command::NextNode => {
+            game_state.set_selected_node(game_state.selected_node_index.saturating_add(1));
+        }
+        Command::PreviousNode => {
+            game_state.set_selected_node(game_state.selected_node_index.saturating_sub(1));
+        }
+        Command::EnterNode => {
+            game_state.navigation_mode = NavigationMode::Node;
+            game_state.selected_unit_index = 0;
+            game_state.unit_scroll_offset = 0;
+        }
+        Command::NextUnit => {
+            game_state.selected_unit_index = game_state
+                .selected_unit_index
+                .saturating_add(1)
+                .min(game_state.selected_node_unit_count().saturating_sub(1));
+        }
+        Command::PreviousUnit => {
+            game_state.selected_unit_index = game_state.selected_unit_index.saturating_sub(1);
+        }
+        Command::JumpToNode(index) => {
+            game_state.set_selected_node(index);
+            game_state.navigation_mode = NavigationMode::Cluster;
+        }
+        Command::BackToCluster => {
+            game_state.navigation_mode = NavigationMode::Cluster;
+        }
+        Command::CreateUnit => {
+            game_state.navigation_mode = NavigationMode::Create;
+            game_state.create_text_buf.clear();
+            game_state.create_target = None;
+            game_state.create_naming = false;
+            game_state.create_count = 0;
+            game_state.create_target_selection = None;
+        }
+        Command::BuildMiner | Command::BuildProcessor => {
+            if !matches!(game_state.navigation_mode, NavigationMode::Create) {
+                run_command(game_state, Command::CreateUnit, k_tx);
+            }
+            game_state.create_target = Some(match command {
+                Command::BuildMiner => CreateTarget::Miner,
+                _ => CreateTarget::Processor,
+            });
+            game_state.create_name_buf.clear();
+            game_state.create_naming = true;
+        }
+        Command::BuildAdvanced => {
+            if let Some(advanced) = storage::get::<Settings>().advanced_unit.clone() {
+                if game_state.credits < advanced.price {
+                    game_state.alert(format!(
+                        "Not enough credits for {} (${})",
+                        advanced.kind, advanced.price
+                    ));
+                } else if units_left() == Some(0) {
+                    let max_units = storage::get::<Settings>().max_units.unwrap_or_default();
+                    game_state.alert(CubeHarvestError::UnitLimit(max_units).to_string());
+                } else {
+                    let name = format!("advanced-{}", rand::rand());
+                    let sent = advanced.build(name).and_then(|object| {
+                        let msg = GameMessage::CreateAdvancedUnit(
+                            Box::new(object),
+                            advanced.api_resource(),
+                        );
+                        Ok(k_tx.blocking_send(msg)?)
+                    });
+                    match sent {
+                        Ok(()) => game_state.credits -= advanced.price,
+                        Err(err) => println!("failed to create {}: {err}", advanced.kind),
+                    }
+                }
+            }
+            game_state.navigation_mode = NavigationMode::Cluster;
+        }
+        Command::SaveBlueprint | Command::LoadBlueprint => {
+            game_state.navigation_mode = NavigationMode::Blueprint(match command {
+                Command::SaveBlueprint => BlueprintAction::Save,
+                _ => BlueprintAction::Load,
+            });
+            game_state.create_text_buf.clear();
+        }
+        Command::DeleteSessionUnits => {
+            let game_resources = storage::get::<GameResources>();
+            for msg in game_resources
+                .pods
+                .iter()
+                .filter(|p| get_session_id(p) == Some(SESSION_ID.as_str()))
+                .filter_map(delete_unit_message)
+            {
+                send_action(game_state, k_tx, msg, "deleting pod");
+            }
+        }
+        Command::UnlockNode => {
+            let settings = storage::get::<Settings>();
+            let Some(node_name) = game_state.selected_node_name() else {
+                return;
+            };
+            if !settings.node_budget
+                || game_state.is_node_unlocked(game_state.selected_node_index, &node_name)
+            {
+                return;
+            }
+            if game_state.credits < settings.node_unlock_price {
+                game_state.alert(format!(
+                    "Not enough credits to unlock {node_name} (${})",
+                    settings.node_unlock_price
+                ));
+                return;
+            }
+            game_state.credits -= settings.node_unlock_price;
+            game_state.unlocked_nodes.insert(node_name);
+            SaveGame::of(game_state).save();
+        }
+        Command::ToggleKeyHints => {
+            game_state.key_hints = !game_state.key_hints;
+        }
+        Command::CycleUnitLabel => {
+            let mut settings = storage::get_mut::<Settings>();
+            settings.unit_label = match settings.unit_label {
+                UnitLabel::Ip => UnitLabel::Name,
+                UnitLabel::Name => UnitLabel::Age,
+                UnitLabel::Age => UnitLabel::None,
+                UnitLabel::None => UnitLabel::Ip,
+            };
+            if let Err(err) = settings.save() {
+                println!("failed to save {SETTINGS_PATH}: {err}");
+            }
+        }
+        Command::DeleteUnit => {
+            game_state.pending_delete = game_state
+                .selected_unit()
+                .and_then(|p| p.metadata.name.clone());
+        }
+        Command::DeleteNodeUnits => {
+            let Some(node_name) = game_state.selected_node_name() else {
+                return;
+            };
+            let names = storage::get::<GameResources>()
+                .pods_on_node(&node_name)
+                .iter()
+                .filter_map(|p| p.metadata.name.clone())
+                .collect::<Vec<_>>();
+            if !names.is_empty() {
+                game_state.pending_bulk_delete = Some(names);
+            }
+        }
+        Command::ToggleListView => {
+            game_state.navigation_mode = match game_state.navigation_mode {
+                NavigationMode::List => NavigationMode::Cluster,
+                _ => NavigationMode::List,
+            };
+        }
+        Command::ToggleOwnerFilter => {
+            let Some(owner) = storage::get::<Settings>().owner.clone() else {
+                game_state.alert("Start with --owner to show only your units");
+                return;
+            };
+            game_state.show_all_owners = !game_state.show_all_owners;
+            let filter = (!game_state.show_all_owners).then_some(owner);
+            send_action(
+                game_state,
+                k_tx,
+                GameMessage::FilterOwner(filter),
+                "filtering units",
+            );
+        }
+        Command::CycleDemoSpeed => {
+            let mut settings = storage::get_mut::<Settings>();
+            settings.demo_speed = match settings.demo_speed() {
+                speed if speed < 2. => 2.,
+                speed if speed < 5. => 5.,
+                _ => 1.,
+            };
+            if let Err(err) = settings.save() {
+                println!("failed to save {SETTINGS_PATH}: {err}");
+            }
+        }
+        Command::ConvertUnit(index) => {
+            let game_resources = storage::get::<GameResources>();
+            let Some(pod) = game_resources.pods.get(index) else {
+                return;
+            };
+            if is_workload_pod(pod) {
+                game_state.alert("Only units created as bare pods can be converted");
+                return;
+            }
+            let target = match get_unit_type(pod).as_deref() {
+                Some("miner") => CreateTarget::Processor,
+                _ => CreateTarget::Miner,
+            };
+            // a converted processor starts from its target as a miner, if
+            // it was one
+            let miner_target = match target {
+                CreateTarget::Miner => get_env_target(pod).unwrap_or_default(),
+                CreateTarget::Processor => String::new(),
+            };
+            if let Some(name) = pod.metadata.name.clone() {
+                game_state.pending_conversion = Some((name, target, miner_target));
+            }
+        }
+        Command::ForceDelete(index) => {
+            let game_resources = storage::get::<GameResources>();
+            game_state.pending_force_delete = game_resources
+                .pods
+                .get(index)
+                .and_then(|p| p.metadata.name.clone());
+        }
+        Command::Quit => game_state.quit_requested = true,
+    }
+}
+
+/// Node filter typed after [/] in the cluster view
+#[derive(Debug, Clone, Default)]
+pub struct NodeSearch {
+    pub query: String,
+    /// Node selected before searching, restored on cancel
+    pub previous_index: usize,
+}
+
+impl NodeSearch {
+    /// Index of the first node whose name contains the query, ignoring case
+    pub fn first_match(&self, game_resources: &GameResources) -> Option<usize> {
+        let query = self.query.to_lowercase();
+        game_resources.nodes.iter().position(|node| {
+            node.metadata
+                .name
+                .as_ref()
+                .is_some_and(|name| name.to_lowercase().contains(&query))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CommandPalette {
+    pub query: String,
+    pub selected: usize,
+}
+
+impl CommandPalette {
+    /// Commands whose label fuzzy matches the query, with their labels
+    pub fn matches(&self, game_resources: &GameResources) -> Vec<(Command, String)> {
+        let mut commands = vec![
+            (Command::NextNode, "Next node".to_string()),
+            (Command::PreviousNode, "Previous node".to_string()),
+            (Command::EnterNode, "Select node".to_string()),
+            (Command::BackToCluster, "Back to cluster".to_string()),
+            (Command::CreateUnit, "Create unit".to_string()),
+            (Command::BuildMiner, "Build miner".to_string()),
+            (Command::BuildProcessor, "Build processor".to_string()),
+            (Command::ToggleKeyHints, "Toggle key hints".to_string()),
+            (Command::CycleUnitLabel, "Cycle unit labels".to_string()),
+            (Command::CycleDemoSpeed, "Cycle demo speed".to_string()),
+            (Command::ToggleListView, "Toggle unit list".to_string()),
+            (
+                Command::ToggleOwnerFilter,
+                "Toggle all owners' units / only mine".to_string(),
+            ),
+            (Command::UnlockNode, "Unlock node".to_string()),
+            (
+                Command::DeleteNodeUnits,
+                "Delete all units on this node".to_string(),
+            ),
+            (Command::SaveBlueprint, "Save blueprint".to_string()),
+            (Command::LoadBlueprint, "Load blueprint".to_string()),
+            (
+                Command::DeleteSessionUnits,
+                "Delete units created this session".to_string(),
+            ),
+            (Command::Quit, "Quit".to_string()),
+        ];
+        if let Some(advanced) = storage::get::<Settings>().advanced_unit.as_ref() {
+            commands.push((Command::BuildAdvanced, format!("Build {}", advanced.kind)));
+        }
+        for (i, node) in game_resources.nodes.iter().enumerate() {
+            let name = node.metadata.name.as_deref().unwrap_or("<unnamed>");
+            commands.push((Command::JumpToNode(i), format!("Jump to node {name}")));
+        }
+        for (i, pod) in game_resources.pods.iter().enumerate() {
+            let (Some(name), Some(unit_type)) = (pod.metadata.name.as_deref(), get_unit_type(pod))
+            else {
+                continue;
+            };
+            // a rescheduled pod would come back as the old type
+            if is_workload_pod(pod) {
+                continue;
+            }
+            let other = match unit_type.as_str() {
+                "miner" => "processor",
+                _ => "miner",
+            };
+            commands.push((
+                Command::ConvertUnit(i),
+                format!("Convert {name} to {other}"),
+            ));
+        }
+        let now = Utc::now();
+        for (i, pod) in game_resources.pods.iter().enumerate() {
+            if let Some(name) = pod.metadata.name.as_deref()
+                && is_stuck_terminating(pod, now)
+            {
+                commands.push((
+                    Command::ForceDelete(i),
+                    format!("Force delete stuck {name}"),
+                ));
+            }
+        }
+        commands.retain(|(_, label)| fuzzy_match(&self.query, label));
+        commands
+    }
+}
+
+/// Case-insensitive subsequence match, e.g. "bmin" matches "Build miner"
+fn fuzzy_match(query: &str, candidate: &str) -> bool {
+    let mut candidate = candidate.chars().flat_map(char::to_lowercase);
+    query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .filter(|c| !c.is_whitespace())
+        .all(|q| candidate.any(|c| c == q))
+}
+
+/// Creates sent to the cluster that haven't shown up in [`GameResources`] yet
+#[derive(Debug, Default)]
+pub struct BuildQueue {
+    pub units: Vec<QueuedUnit>,
+}
+
+#[derive(Debug)]
+pub struct QueuedUnit {
+    pub name: String,
+    pub unit_type: String,
+}
+
+/// Kubeconfig contexts to pick from in the main menu
+#[derive(Debug, Default)]
+pub struct KubeContexts {
+    pub names: Vec<String>,
+    pub selected: usize,
+    /// The context the cluster is followed through
+    pub active: Option<String>,
+    /// Requested with `GameMessage::SwitchContext`, not answered yet
+    pub switching: Option<String>,
+    pub error: Option<String>,
+}
+
+impl KubeContexts {
+    pub fn load() -> Self {
+        let (names, active) = kube_contexts();
+        let selected = active
+            .as_ref()
+            .and_then(|active| names.iter().position(|name| name == active))
+            .unwrap_or(0);
+        Self {
+            names,
+            selected,
+            active,
+            switching: None,
+            error: None,
+        }
+    }
+}
+
+/// What [`GameState`] keeps across sessions in [`SAVE_PATH`], the view state
+/// and text buffers start fresh every launch
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SaveGame {
+    pub credits: usize,
+    /// Nodes bought with `Settings::node_budget`
+    pub unlocked_nodes: HashSet<String>,
+}
+
+impl SaveGame {
+    pub fn of(game_state: &GameState) -> Self {
+        Self {
+            credits: game_state.credits,
+            unlocked_nodes: game_state.unlocked_nodes.clone(),
+        }
+    }
+
+    /// A corrupt save starts from scratch, a missing one from the files of
+    /// older versions if there are any
+    pub fn load() -> Self {
+        match std::fs::read_to_string(SAVE_PATH) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|err| {
+                println!("ignore corrupt {SAVE_PATH}: {err}");
+                Self::default()
+            }),
+            Err(_) => Self {
+                credits: std::fs::read_to_string(LEGACY_CREDITS_PATH)
+                    .ok()
+                    .and_then(|s| s.trim().parse().ok())
+                    .unwrap_or(0),
+                unlocked_nodes: std::fs::read_to_string(LEGACY_UNLOCKED_NODES_PATH)
+                    .ok()
+                    .and_then(|s| serde_json::from_str(&s).ok())
+                    .unwrap_or_default(),
+            },
+        }
+    }
+
+    pub fn save(&self) {
+        let result = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::from)
+            .and_then(|s| std::fs::write(SAVE_PATH, s));
+        if let Err(err) = result {
+            println!("failed to save {SAVE_PATH}: {err}");
+        }
+    }
+}
+
+/// Send `msg` to the cluster, a closed channel is logged and shown as the
+/// [`GameState::action_error`]. Returns whether it was sent.
+pub fn send_action(
+    game_state: &mut GameState,
+    k_tx: &Sender<GameMessage>,
+    msg: GameMessage,
+    request: &str,
+) -> bool {
+    match k_tx.blocking_send(msg) {
+        Ok(()) => true,
+        Err(err) => {
+            let reason = format!(
+                "failed to request {request}: {}",
+                CubeHarvestError::from(err)
+            );
+            println!("{reason}");
+            game_state.action_error = Some(reason);
+            false
+        }
+    }
+}
+
+/// Create the units configured in Create mode, charging their combined price
+/// up front. Units that couldn't be sent are refunded.
+pub fn commit_create(
+    game_state: &mut GameState,
+    target: &CreateTarget,
+    node_name: Option<String>,
+    k_tx: &Sender<GameMessage>,
+) -> Result<(), CubeHarvestError> {
+    let batch = game_state.create_batch();
+    let price = game_state.create_price(target) / batch;
+    game_state.credits -= price * batch;
+    println!(
+        "Create {batch} {target:?} -> {}",
+        game_state.create_text_buf
+    );
+    for i in 0..batch {
+        let sent = create_unit(game_state, target, i).and_then(|mut astro_unit| {
+            if storage::get::<Settings>().node_budget
+                && let Some(spec) = astro_unit.spec.as_mut()
+            {
+                spec.node_name = node_name.clone();
+            }
+            send_create_pod(k_tx, astro_unit)
+        });
+        if let Err(err) = sent {
+            game_state.credits += price * (batch - i);
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// The `index`th unit of the batch, see [`GameState::create_unit_name`]
+fn create_unit(
+    game_state: &GameState,
+    target: &CreateTarget,
+    index: usize,
+) -> Result<Pod, CubeHarvestError> {
+    let name = if game_state.create_name_buf.is_empty() {
+        format!("{}-{}", target.unit_type(), rand::rand())
+    } else {
+        game_state.create_unit_name(index)
+    };
+    build_unit(
+        name,
+        target,
+        game_state.create_text_buf.clone(),
+        storage::get::<Settings>().owner.as_deref(),
+        &storage::get::<GameConfig>(),
+    )
+}
+
+/// Saved layout of game units, stored under [`BLUEPRINTS_DIR`]
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Blueprint {
+    pub units: Vec<BlueprintUnit>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BlueprintUnit {
+    pub name: String,
+    pub unit_type: String,
+    /// Index of the node the unit ran on, wrapped around the node count of the
+    /// cluster the blueprint is loaded on
+    pub node_index: Option<usize>,
+    /// Name of the processor (within the blueprint) a miner targets
+    pub target: Option<String>,
+}
+
+/// A blueprint miner which is created once its processor has an IP
+#[derive(Debug, Clone)]
+pub struct PendingMiner {
+    pub name: String,
+    processor_name: String,
+    pub node_name: Option<String>,
+}
+
+impl Blueprint {
+    pub fn path(name: &str) -> PathBuf {
+        Path::new(BLUEPRINTS_DIR).join(format!("{name}.json"))
+    }
+
+    /// Capture all game units of the current cluster
+    pub fn capture(game_resources: &GameResources) -> Self {
+        let processor_names = game_resources
+            .pods
+            .iter()
+            .filter(|p| get_unit_type(p).as_deref() == Some("processor"))
+            .filter_map(|p| Some((processor_targets(p), p.metadata.name.as_deref()?)))
+            .flat_map(|(targets, name)| {
+                targets
+                    .into_iter()
+                    .map(move |target| (target, name.to_string()))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let units = game_resources
+            .pods
+            .iter()
+            .filter_map(|p| {
+                let unit_type = get_unit_type(p)?;
+                let node_name = p.spec.as_ref().and_then(|s| s.node_name.as_deref());
+                let node_index = game_resources
+                    .nodes
+                    .iter()
+                    .position(|n| n.metadata.name.as_deref() == node_name);
+                let target = match unit_type.as_str() {
+                    "miner" => get_unit_target(p)
+                        .and_then(|t| processor_names.get(target_key(&t)).cloned()),
+                    _ => None,
+                };
+                Some(BlueprintUnit {
+                    name: p.metadata.name.clone()?,
+                    unit_type,
+                    node_index,
+                    target,
+                })
+            })
+            .collect();
+
+        Self { units }
+    }
+
+    pub fn save(&self, name: &str) -> std::io::Result<()> {
+        std::fs::create_dir_all(BLUEPRINTS_DIR)?;
+        std::fs::write(Self::path(name), serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn load(name: &str) -> std::io::Result<Self> {
+        let content = std::fs::read_to_string(Self::path(name))?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Request creating every unit of the blueprint. Processors are created
+    /// right away, miners targeting one of them are returned so they can be
+    /// created once the new processor has an IP.
+    pub fn apply(
+        &self,
+        game_resources: &GameResources,
+        k_tx: &Sender<GameMessage>,
+    ) -> Vec<PendingMiner> {
+        let mut taken_names = game_resources
+            .pods
+            .iter()
+            .filter_map(|p| p.metadata.name.clone())
+            .collect::<HashSet<_>>();
+        let mut unique_name = |name: &str| {
+            let mut candidate = name.to_string();
+            while taken_names.contains(&candidate) {
+                candidate = format!("{name}-{}", rand::rand());
+            }
+            taken_names.insert(candidate.clone());
+            candidate
+        };
+        let node_name = |unit: &BlueprintUnit| {
+            let nodes = &game_resources.nodes;
+            let index = unit.node_index?;
+            nodes.get(index % nodes.len().max(1))?.metadata.name.clone()
+        };
+
+        // blueprint processor name -> created processor name
+        let mut processors = HashMap::new();
+        let mut pending = Vec::new();
+        for unit in self.units.iter().filter(|u| u.unit_type == "processor") {
+            let name = unique_name(&unit.name);
+            let created = send_create_unit(
+                k_tx,
+                name.clone(),
+                &CreateTarget::Processor,
+                String::new(),
+                node_name(unit),
+            );
+            match created {
+                Ok(()) => {
+                    processors.insert(unit.name.clone(), name);
+                }
+                Err(err) => println!("failed to create blueprint unit {}: {err}", unit.name),
+            }
+        }
+        for unit in self.units.iter().filter(|u| u.unit_type != "processor") {
+            let Some(target) = CreateTarget::from_unit_type(&unit.unit_type) else {
+                println!(
+                    "skip blueprint unit {} with unknown type {}",
+                    unit.name, unit.unit_type
+                );
+                continue;
+            };
+            let name = unique_name(&unit.name);
+            match unit.target.as_ref().and_then(|t| processors.get(t)) {
+                Some(processor_name) => pending.push(PendingMiner {
+                    name,
+                    processor_name: processor_name.clone(),
+                    node_name: node_name(unit),
+                }),
+                None => {
+                    if let Err(err) =
+                        send_create_unit(k_tx, name, &target, String::new(), node_name(unit))
+                    {
+                        println!("failed to create blueprint unit {}: {err}", unit.name);
+                    }
+                }
+            }
+        }
+
+        pending
+    }
+}
+
+/// Units that may still be created under `Settings::max_units`, counting the
+/// ones in the [`BuildQueue`] that aren't listed yet
+pub fn units_left() -> Option<usize> {
+    let max_units = storage::get::<Settings>().max_units?;
+    // nothing is listed before the first snapshot arrived
+    let game_resources = storage::try_get::<GameResources>();
+    let pods = game_resources.as_ref().map_or(&[][..], |g| &g.pods[..]);
+    let listed = game_resources.as_ref().map_or(0, |g| g.unit_count());
+    let queued = storage::get::<BuildQueue>()
+        .units
+        .iter()
+        .filter(|u| {
+            !pods
+                .iter()
+                .any(|p| p.metadata.name.as_ref() == Some(&u.name))
+        })
+        .count();
+    Some(max_units.saturating_sub(listed + queued))
+}
+
+/// Send the create to the cluster and show it in the [`BuildQueue`] until it
+/// shows up, refused once `Settings::max_units` is reached
+fn send_create_pod(k_tx: &Sender<GameMessage>, pod: Pod) -> Result<(), CubeHarvestError> {
+    if units_left() == Some(0) {
+        let max_units = storage::get::<Settings>().max_units.unwrap_or_default();
+        return Err(CubeHarvestError::UnitLimit(max_units));
+    }
+    let queued = QueuedUnit {
+        name: pod.metadata.name.clone().unwrap_or_default(),
+        unit_type: get_unit_type(&pod).unwrap_or_default(),
+    };
+    let settings = storage::get::<Settings>();
+    let msg = match settings.unit_workload {
+        _ if settings.stateful_processors && queued.unit_type == "processor" => {
+            let (stateful_set, service) = stateful_set_for(pod)?;
+            GameMessage::CreateStatefulSet(Box::new(stateful_set), Box::new(service))
+        }
+        UnitWorkload::Pod => GameMessage::CreatePod(Box::new(pod)),
+        UnitWorkload::Deployment => GameMessage::CreateDeployment(Box::new(deployment_for(pod)?)),
+    };
+    k_tx.blocking_send(msg)?;
+    storage::get_mut::<BuildQueue>().units.push(queued);
+    Ok(())
+}
+
+fn send_create_unit(
+    k_tx: &Sender<GameMessage>,
+    name: String,
+    target: &CreateTarget,
+    miner_target: String,
+    node_name: Option<String>,
+) -> Result<(), CubeHarvestError> {
+    let mut astro_unit = build_unit(
+        name,
+        target,
+        miner_target,
+        storage::get::<Settings>().owner.as_deref(),
+        &storage::get::<GameConfig>(),
+    )?;
+    if let Some(spec) = astro_unit.spec.as_mut() {
+        spec.node_name = node_name;
+    }
+    send_create_pod(k_tx, astro_unit)?;
+    Ok(())
+}
+
+/// IP of the processor unit named `processor_name`, matched by unit name so
+/// deployment pods are found too
+fn processor_ip<'a>(pods: &'a [Pod], processor_name: &str) -> Option<&'a str> {
+    pods.iter()
+        .find(|p| get_unit_name(p) == Some(processor_name))
+        .and_then(get_unit_ip)
+}
+
+/// Create the pending blueprint miners whose processor can be targeted, with
+/// [`MinerTarget::Ip`] that's once it got an IP
+pub fn flush_pending_miners(game_state: &mut GameState, k_tx: &Sender<GameMessage>) {
+    if game_state.pending_blueprint_miners.is_empty() {
+        return;
+    }
+    let game_resources = storage::get::<GameResources>();
+    let mode = storage::get::<Settings>().miner_target;
+    game_state.pending_blueprint_miners.retain(|miner| {
+        let miner_target = match mode {
+            MinerTarget::Ip => processor_ip(&game_resources.pods, &miner.processor_name),
+            MinerTarget::Name => Some(miner.processor_name.as_str()),
+        };
+        match miner_target {
+            Some(miner_target) => {
+                let created = send_create_unit(
+                    k_tx,
+                    miner.name.clone(),
+                    &CreateTarget::Miner,
+                    miner_target.to_string(),
+                    miner.node_name.clone(),
+                );
+                if let Err(err) = created {
+                    println!("failed to create blueprint unit {}: {err}", miner.name);
+                }
+                false
+            }
+            None => true,
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn deployment_processor_is_found_by_unit_name() {
+        let pod: Pod = serde_json::from_value(json!({
+            "metadata": {
+                "name": "processor-1-5d8f7c9b4-x2kqp",
+                "labels": {
+                    "cube-harvest.io/unit-type": "processor",
+                    "cube-harvest.io/unit-name": "processor-1",
+                },
+            },
+            "status": { "podIP": "10.0.0.1" },
+        }))
+        .unwrap();
+        let pods = [pod];
+        assert_eq!(processor_ip(&pods, "processor-1"), Some("10.0.0.1"));
+        assert_eq!(processor_ip(&pods, "processor-2"), None);
+    }
+}